@@ -4,8 +4,11 @@ use std::process::Command;
 use wasm_bindgen_cli_support::Bindgen;
 
 fn main() -> anyhow::Result<()> {
-    embuild::build::CfgArgs::output_propagated("ESP_IDF")?;
-    embuild::build::LinkArgs::output_propagated("ESP_IDF")?;
+    #[cfg(feature = "esp")]
+    {
+        embuild::build::CfgArgs::output_propagated("ESP_IDF")?;
+        embuild::build::LinkArgs::output_propagated("ESP_IDF")?;
+    }
 
     let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
     let frontend_crate_dir = Path::new("./frontend");
@@ -16,6 +19,7 @@ fn main() -> anyhow::Result<()> {
 
     // Rerun build script if frontend crate changed
     println!("cargo:rerun-if-changed={}", frontend_crate_dir.display());
+    println!("cargo:rerun-if-changed=./prog_container");
     println!(
         "cargo:rerun-if-changed={}",
         Path::new("./Cargo.lock").display()
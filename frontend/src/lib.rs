@@ -1,16 +1,131 @@
 use animation_lang::compiler::FromSource;
 use animation_lang::program::Program;
+use animation_lang::vm::{VMState, VMStateConfig, VM};
+use prog_container::{Container, Metadata};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen(getter_with_clone)]
 pub struct CompileResult(pub Vec<u8>, pub String);
 
+/// Compiled program is wrapped in container, which is accepted by device
 #[wasm_bindgen]
-pub fn compile_prog(source: &str) -> Result<CompileResult, JsValue> {
+pub fn compile_prog(
+    source: &str,
+    name: Option<String>,
+    author: Option<String>,
+    default_fps: Option<u8>,
+) -> Result<CompileResult, JsValue> {
     let program = Program::from_source(source).map_err(|e| e.to_string())?;
-
-    Ok(CompileResult(
+    let container = Container::new(
+        Metadata {
+            name: name.unwrap_or_default(),
+            author: author.unwrap_or_default(),
+            default_fps,
+        },
         program.code().to_vec(),
-        format!("{:?}", program),
-    ))
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(CompileResult(container.encode(), format!("{:?}", program)))
+}
+
+/// Runs compiled program in browser frame by frame, same way device does
+#[wasm_bindgen]
+pub struct Simulator {
+    bin_prog: Vec<u8>,
+    led_quantity: usize,
+    seed: u32,
+    // `None` when program ended or failed
+    vm_state: Option<VMState>,
+    frame: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl Simulator {
+    /// Runs program from container, emitted by `compile_prog`.
+    /// Random numbers are taken from generator seeded by `seed`, so runs are reproducible
+    #[wasm_bindgen(constructor)]
+    pub fn new(container: Vec<u8>, led_quantity: usize, seed: u32) -> Result<Simulator, JsValue> {
+        let container = Container::decode(&container).map_err(|e| e.to_string())?;
+        let mut simulator = Simulator {
+            bin_prog: container.into_code(),
+            led_quantity,
+            seed,
+            vm_state: None,
+            frame: Vec::with_capacity(led_quantity * 3),
+        };
+        simulator.restart();
+
+        Ok(simulator)
+    }
+
+    /// Starts program from beginning
+    pub fn restart(&mut self) {
+        self.vm_state = Some(VM::new(self.led_quantity, Default::default()).start(
+            Program::from_binary(self.bin_prog.clone()),
+            VMStateConfig {
+                local_instruction_limit: Some(1_000_000),
+                rng: Box::new(StdRng::seed_from_u64(self.seed.into())),
+                ..Default::default()
+            },
+        ));
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn led_quantity(&self) -> usize {
+        self.led_quantity
+    }
+
+    /// Changes strip length, program is restarted like on device
+    #[wasm_bindgen(setter)]
+    pub fn set_led_quantity(&mut self, led_quantity: usize) {
+        self.led_quantity = led_quantity;
+        self.restart();
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    /// Changes seed of random numbers, program is restarted
+    #[wasm_bindgen(setter)]
+    pub fn set_seed(&mut self, seed: u32) {
+        self.seed = seed;
+        self.restart();
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn running(&self) -> bool {
+        self.vm_state.is_some()
+    }
+
+    /// Next frame as `[r, g, b]` bytes of every led, `undefined` when program ended,
+    /// runtime errors of program are thrown
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, JsValue> {
+        let vm_state = match &mut self.vm_state {
+            Some(vm_state) => vm_state,
+            None => return Ok(None),
+        };
+
+        match vm_state.next() {
+            Some(Ok(frame)) => {
+                self.frame.clear();
+                for c in frame {
+                    self.frame.extend_from_slice(&[c.r, c.g, c.b]);
+                }
+                Ok(Some(self.frame.clone()))
+            }
+            Some(Err(e)) => {
+                self.vm_state = None;
+                Err(format!("{:?}", e).into())
+            }
+            None => {
+                self.vm_state = None;
+                Ok(None)
+            }
+        }
+    }
 }
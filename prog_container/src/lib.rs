@@ -0,0 +1,186 @@
+//! Container of compiled animation-lang program, shared by firmware and frontend.
+//!
+//! Layout, integers are little endian:
+//!
+//! | size         | field                                       |
+//! |--------------|---------------------------------------------|
+//! | 4            | magic `ALNG`                                |
+//! | 1            | container version                           |
+//! | 1            | language version of bytecode                |
+//! | 1            | default fps, zero means not set             |
+//! | 1 + len      | name, length prefixed utf-8                 |
+//! | 1 + len      | author, length prefixed utf-8               |
+//! | 4 + len      | bytecode, length prefixed                   |
+//! | 4            | CRC-32 of all preceding bytes               |
+
+use thiserror::Error;
+
+pub const MAGIC: [u8; 4] = *b"ALNG";
+pub const CONTAINER_VERSION: u8 = 1;
+/// Version of bytecode, bumped when instructions of animation-lang change
+pub const LANGUAGE_VERSION: u8 = 1;
+/// Max length of name and author in bytes
+pub const MAX_TEXT_LEN: usize = u8::MAX as usize;
+
+const CHECKSUM_LEN: usize = 4;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ContainerError {
+    #[error("Not a program container, expected magic {MAGIC:?}")]
+    BadMagic,
+    #[error("Unsupported container version {0}, supported {CONTAINER_VERSION}")]
+    UnsupportedContainer(u8),
+    #[error("Program is compiled for language version {0}, supported {LANGUAGE_VERSION}")]
+    UnsupportedLanguage(u8),
+    #[error("Program container is truncated")]
+    Truncated,
+    #[error("Program container has {0} unexpected trailing bytes")]
+    TrailingBytes(usize),
+    #[error("Checksum mismatch, program container is corrupt")]
+    ChecksumMismatch,
+    #[error("{0} should be valid utf-8")]
+    InvalidText(&'static str),
+    #[error("{0} should be at most {MAX_TEXT_LEN} bytes long")]
+    TextTooLong(&'static str),
+}
+
+/// Information about program, which is not needed to run it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    pub name: String,
+    pub author: String,
+    /// Frames per second program is designed for
+    pub default_fps: Option<u8>,
+}
+
+/// Fields are private, so every container passed checks of `new` or `decode` and can be encoded
+#[derive(Debug, Clone, PartialEq)]
+pub struct Container {
+    metadata: Metadata,
+    code: Vec<u8>,
+}
+
+impl Container {
+    /// Wraps bytecode of current language version
+    pub fn new(metadata: Metadata, code: Vec<u8>) -> Result<Self, ContainerError> {
+        if metadata.name.len() > MAX_TEXT_LEN {
+            return Err(ContainerError::TextTooLong("name"));
+        }
+        if metadata.author.len() > MAX_TEXT_LEN {
+            return Err(ContainerError::TextTooLong("author"));
+        }
+
+        Ok(Self { metadata, code })
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    pub fn into_code(self) -> Vec<u8> {
+        self.code
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.code.len() + 32);
+        data.extend_from_slice(&MAGIC);
+        data.push(CONTAINER_VERSION);
+        data.push(LANGUAGE_VERSION);
+        data.push(self.metadata.default_fps.unwrap_or(0));
+        for text in [&self.metadata.name, &self.metadata.author] {
+            data.push(text.len() as u8);
+            data.extend_from_slice(text.as_bytes());
+        }
+        data.extend_from_slice(&(self.code.len() as u32).to_le_bytes());
+        data.extend_from_slice(&self.code);
+        data.extend_from_slice(&crc32(&data).to_le_bytes());
+
+        data
+    }
+
+    /// Rejects containers of other versions, corrupt or truncated ones
+    pub fn decode(data: &[u8]) -> Result<Self, ContainerError> {
+        if !data.starts_with(&MAGIC) {
+            return Err(ContainerError::BadMagic);
+        }
+        // Layout of other container versions is unknown, so version is checked first
+        match data.get(MAGIC.len()) {
+            None => return Err(ContainerError::Truncated),
+            Some(&version) if version != CONTAINER_VERSION => {
+                return Err(ContainerError::UnsupportedContainer(version))
+            }
+            Some(_) => (),
+        }
+        if data.len() < MAGIC.len() + 1 + CHECKSUM_LEN {
+            return Err(ContainerError::Truncated);
+        }
+        let (content, checksum) = data.split_at(data.len() - CHECKSUM_LEN);
+        if crc32(content).to_le_bytes() != checksum {
+            return Err(ContainerError::ChecksumMismatch);
+        }
+
+        let mut reader = Reader(&content[MAGIC.len() + 1..]);
+        let language_version = reader.byte()?;
+        if language_version != LANGUAGE_VERSION {
+            return Err(ContainerError::UnsupportedLanguage(language_version));
+        }
+        let default_fps = Some(reader.byte()?).filter(|&fps| fps != 0);
+        let name = reader.text("name")?;
+        let author = reader.text("author")?;
+        let code_len = u32::from_le_bytes(reader.take(4)?.try_into().unwrap()) as usize;
+        let code = reader.take(code_len)?.to_vec();
+        if !reader.0.is_empty() {
+            return Err(ContainerError::TrailingBytes(reader.0.len()));
+        }
+
+        Ok(Self {
+            metadata: Metadata {
+                name,
+                author,
+                default_fps,
+            },
+            code,
+        })
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ContainerError> {
+        if self.0.len() < len {
+            return Err(ContainerError::Truncated);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, ContainerError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn text(&mut self, field: &'static str) -> Result<String, ContainerError> {
+        let len = self.byte()? as usize;
+
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| ContainerError::InvalidText(field))
+    }
+}
+
+/// CRC-32 (IEEE 802.3), same as used by zip and png
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
@@ -0,0 +1,98 @@
+use prog_container::{crc32, Container, ContainerError, Metadata, LANGUAGE_VERSION, MAGIC};
+
+fn container() -> Container {
+    Container::new(
+        Metadata {
+            name: "rainbow".to_string(),
+            author: "maba_kalox".to_string(),
+            default_fps: Some(30),
+        },
+        vec![0xe0, 0x50, 0x0f, 0x00],
+    )
+    .unwrap()
+}
+
+#[test]
+fn round_trip() {
+    let container = container();
+
+    assert_eq!(Container::decode(&container.encode()), Ok(container));
+}
+
+#[test]
+fn round_trip_without_metadata() {
+    let container = Container::new(Default::default(), vec![1, 2, 3]).unwrap();
+
+    assert_eq!(Container::decode(&container.encode()), Ok(container));
+}
+
+#[test]
+fn rejects_bare_bytecode() {
+    assert_eq!(
+        Container::decode(&[0xe0, 0x50, 0x0f, 0x00]),
+        Err(ContainerError::BadMagic)
+    );
+    assert_eq!(Container::decode(&[]), Err(ContainerError::BadMagic));
+}
+
+#[test]
+fn rejects_corrupt_container() {
+    let mut data = container().encode();
+    let last_code_byte = data.len() - 5;
+    data[last_code_byte] ^= 0xff;
+
+    assert_eq!(
+        Container::decode(&data),
+        Err(ContainerError::ChecksumMismatch)
+    );
+}
+
+#[test]
+fn rejects_truncated_container() {
+    let data = container().encode();
+
+    assert_eq!(
+        Container::decode(&data[..MAGIC.len()]),
+        Err(ContainerError::Truncated)
+    );
+    assert_eq!(
+        Container::decode(&data[..data.len() - 1]),
+        Err(ContainerError::ChecksumMismatch)
+    );
+}
+
+#[test]
+fn rejects_other_versions() {
+    let mut data = container().encode();
+    data[MAGIC.len()] += 1;
+
+    assert_eq!(
+        Container::decode(&data),
+        Err(ContainerError::UnsupportedContainer(2))
+    );
+
+    // Language version is covered by checksum, so container is built by hand
+    let mut data = container().encode();
+    data.truncate(data.len() - 4);
+    data[MAGIC.len() + 1] = LANGUAGE_VERSION + 1;
+    let checksum = crc32(&data);
+    data.extend_from_slice(&checksum.to_le_bytes());
+
+    assert_eq!(
+        Container::decode(&data),
+        Err(ContainerError::UnsupportedLanguage(LANGUAGE_VERSION + 1))
+    );
+}
+
+#[test]
+fn rejects_too_long_metadata() {
+    let metadata = Metadata {
+        name: "x".repeat(256),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        Container::new(metadata, Vec::new()),
+        Err(ContainerError::TextTooLong("name"))
+    );
+}
@@ -0,0 +1,130 @@
+use crate::sub_modules::http_router::ApiResponse;
+use crate::sub_modules::led_sink::RecordingSink;
+use crate::sub_modules::led_strip_animations::{AnimationConfig, LedStripAnimation};
+use crate::sub_modules::mqtt;
+use crate::sub_modules::realtime::RealtimeReceiver;
+use crate::sub_modules::scheduler::Scheduler;
+use crate::sub_modules::web_server::api_router;
+use crate::sub_modules::wifi_manager::fake_wifi::FakeWifiDriver;
+use crate::sub_modules::wifi_manager::WifiManager;
+use crate::{
+    load_mqtt_config, load_outputs_config, load_realtime_config, migrate_prog_library,
+    IntoLogLevel, T_CONFIG,
+};
+use anyhow::Result;
+use embedded_svc::http::Method;
+use log::error;
+use std::sync::mpsc;
+
+// Address of emulated web server, can be overridden by `HOST_HTTP_ADDR` env variable
+const DEFAULT_HTTP_ADDR: &str = "127.0.0.1:8080";
+// Frames kept by every emulated led output
+const RECORDED_FRAMES: usize = 256;
+
+/// Runs firmware logic on host, with in-memory storage, fake wifi and led strip
+/// printed to terminal if `HOST_ANSI_STRIP` env variable is set
+pub fn main() -> Result<()> {
+    env_logger::Builder::new()
+        .filter_level(T_CONFIG.log_level.parse_loglevel()?)
+        .init();
+
+    let (_wifi_manager_thread, wifi_manager_api) =
+        WifiManager::new(FakeWifiDriver::new()?).daemon(64 * 1024)?;
+
+    migrate_prog_library();
+
+    let (tx, rx) = mpsc::sync_channel(0);
+
+    let (_scheduler_thread, scheduler_api) = Scheduler::new(tx.clone()).daemon(64 * 1024)?;
+
+    let outputs_config = load_outputs_config()?;
+
+    let _realtime_thread = RealtimeReceiver::new(
+        load_realtime_config(),
+        outputs_config.total_led_quantity(),
+        tx.clone(),
+    )
+    .daemon(64 * 1024)?;
+
+    let _mqtt_thread = mqtt::start(
+        load_mqtt_config(),
+        tx.clone(),
+        outputs_config.total_led_quantity(),
+        64 * 1024,
+    )
+    .unwrap_or_else(|e| {
+        error!("Failed to start mqtt: {:?}", e);
+        None
+    });
+
+    let mut router = api_router(tx, wifi_manager_api, scheduler_api, outputs_config.clone());
+
+    let print_ansi = std::env::var_os("HOST_ANSI_STRIP").is_some();
+    let sinks: Vec<RecordingSink> = outputs_config
+        .outputs
+        .iter()
+        .map(|_| {
+            let sink = RecordingSink::new(RECORDED_FRAMES);
+            if print_ansi {
+                sink.with_ansi_output()
+            } else {
+                sink
+            }
+        })
+        .collect();
+
+    // Emulated led strip, last frame of all outputs as `[r, g, b, w]` pixels
+    router.route("/host/frame", Method::Get, {
+        let sinks = sinks.clone();
+        move |_| {
+            let frame: Vec<[u8; 4]> = sinks
+                .iter()
+                .flat_map(|sink| sink.last_frame().unwrap_or_default())
+                .map(|pixel| [pixel.r, pixel.g, pixel.b, pixel.a.0])
+                .collect();
+
+            ApiResponse::json(&frame)
+        }
+    });
+
+    // Recorded frames of first output as image, one row per frame
+    router.route("/host/strip.ppm", Method::Get, {
+        let sinks = sinks.clone();
+        move |_| {
+            let mut image = Vec::new();
+            if let Some(sink) = sinks.first() {
+                sink.write_ppm(&mut image)?;
+            }
+
+            Ok(ApiResponse::new(200)
+                .with_content_type("image/x-portable-pixmap")
+                .with_body(image))
+        }
+    });
+
+    router.route("/host/frames", Method::Delete, {
+        let sinks = sinks.clone();
+        move |_| {
+            sinks.iter().for_each(RecordingSink::clear);
+
+            Ok(ApiResponse::ok())
+        }
+    });
+
+    std::thread::spawn(move || {
+        LedStripAnimation::new(
+            sinks,
+            &outputs_config,
+            AnimationConfig {
+                led_quantity: outputs_config.total_led_quantity(),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .led_strip_loop(rx)
+        .unwrap()
+    });
+
+    let addr = std::env::var("HOST_HTTP_ADDR").unwrap_or_else(|_| DEFAULT_HTTP_ADDR.to_string());
+    router.serve(&addr)
+}
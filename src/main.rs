@@ -1,23 +1,47 @@
+#[cfg(all(feature = "esp", feature = "host"))]
+compile_error!("Features \"esp\" and \"host\" are mutually exclusive");
+
 use anyhow::bail;
 use anyhow::Result;
+#[cfg(feature = "esp")]
 use esp_idf_hal::gpio;
+#[cfg(feature = "esp")]
 use esp_idf_hal::peripherals::Peripherals;
+#[cfg(feature = "esp")]
 use esp_idf_svc::sntp::SyncStatus;
+#[cfg(feature = "esp")]
 use esp_idf_svc::wifi::WifiEvent;
+#[cfg(feature = "esp")]
 use esp_idf_svc::{eventloop::EspSystemEventLoop, log::EspLogger};
+#[cfg(feature = "esp")]
 use esp_idf_sys::{self as _, esp}; // Dont remove it, required for binstart
+use log::error;
+#[cfg(feature = "esp")]
 use std::ffi::CString;
+#[cfg(feature = "esp")]
 use std::sync::mpsc;
+#[cfg(feature = "esp")]
 use std::time::Duration;
 
+#[cfg(feature = "host")]
+mod host;
 mod sub_modules;
 
-use crate::sub_modules::esp_sntp_wrapper::EspSntpWrapper;
-use crate::sub_modules::led_strip_animations::AnimationConfig;
-use crate::sub_modules::wifi_manager::wifi_states::WifiState;
-use sub_modules::led_strip_animations::LedStripAnimation;
-use sub_modules::web_server::web_server;
-use sub_modules::wifi_manager::WifiManager;
+use crate::sub_modules::led_outputs::OutputsConfig;
+use crate::sub_modules::mqtt::MqttConfig;
+use crate::sub_modules::prog_library;
+use crate::sub_modules::realtime::RealtimeConfig;
+#[cfg(feature = "esp")]
+use crate::sub_modules::{
+    esp_sntp_wrapper::EspSntpWrapper,
+    led_sink::RmtSink,
+    led_strip_animations::{AnimationConfig, LedStripAnimation},
+    mqtt,
+    realtime::RealtimeReceiver,
+    scheduler::Scheduler,
+    web_server::web_server,
+    wifi_manager::{wifi_states::EspWifiDriver, WifiManager},
+};
 
 #[toml_cfg::toml_config]
 struct TConfig {
@@ -30,6 +54,10 @@ struct TConfig {
     #[default(150)]
     led_quantity: usize,
 
+    // Led strips in form "pin:rmt_channel:led_quantity,...", empty - single strip on gpio6
+    #[default("")]
+    led_outputs: &'static str,
+
     #[default("error")]
     log_level: &'static str,
 
@@ -40,6 +68,12 @@ struct TConfig {
     mdns_instance_name: &'static str,
 }
 
+#[cfg(feature = "host")]
+fn main() -> Result<()> {
+    host::main()
+}
+
+#[cfg(feature = "esp")]
 fn main() -> Result<()> {
     // Temporary. Will disappear once ESP-IDF 4.4 is released, but for now it is necessary to call this function once,
     // or else some patches to the runtime implemented by esp-idf-sys might not link properly.
@@ -70,12 +104,12 @@ fn main() -> Result<()> {
         }
     })?;
     // Create wifi manager instance, it will start AP and if credentials stored - connect to STA
-    let wifi_manager = WifiManager::new(peripherals.modem, sysloop)?;
+    let wifi_manager = WifiManager::new(EspWifiDriver::new(peripherals.modem, sysloop)?);
 
     // Start up sntp to sync time
     let sntp = EspSntpWrapper::new_default()?;
     // Wait for sntp to sync, if we have internet connection
-    if matches!(wifi_manager.state, WifiState::Connected(_)) {
+    if wifi_manager.is_connected()? {
         sntp.wait_status_with_timeout(Duration::from_secs(20), |status| {
             matches!(status, SyncStatus::Completed)
         })?;
@@ -84,33 +118,136 @@ fn main() -> Result<()> {
     // Daemonize wifi manager, so it run in background
     let (wifi_manager_thread, wifi_manager_api) = wifi_manager.daemon(5 * 1024)?;
 
+    migrate_prog_library();
+
+    // Stored outputs may fail to initialise, while compile time ones are known to work.
+    // Outputs are resolved before other subsystems start, so all of them use outputs, which drive leds
+    let outputs_config = load_outputs_config()?;
+    let (sinks, outputs_config) = match rmt_sinks(&outputs_config) {
+        Ok(sinks) => (sinks, outputs_config),
+        Err(e) => {
+            error!(
+                "Failed to initialise led outputs, using default ones: {:?}",
+                e
+            );
+            let default_outputs = default_outputs_config()?;
+            (rmt_sinks(&default_outputs)?, default_outputs)
+        }
+    };
+
     let (tx, rx) = mpsc::sync_channel(0);
-    let (applied_config_tx, applied_config_rx) = mpsc::sync_channel(0);
 
-    let _httpd = web_server(tx, applied_config_rx, wifi_manager_api)?;
+    // Daemonize scheduler, it feeds scheduled programs and configs into animation thread
+    let (scheduler_thread, scheduler_api) = Scheduler::new(tx.clone()).daemon(6 * 1024)?;
+
+    // Daemonize realtime receiver, streamed pixels override programs
+    let realtime_thread = RealtimeReceiver::new(
+        load_realtime_config(),
+        outputs_config.total_led_quantity(),
+        tx.clone(),
+    )
+    .daemon(4 * 1024)?;
+
+    // Mqtt bridge is optional, firmware works without broker
+    let _mqtt_thread = mqtt::start(
+        load_mqtt_config(),
+        tx.clone(),
+        outputs_config.total_led_quantity(),
+        6 * 1024,
+    )
+    .unwrap_or_else(|e| {
+        error!("Failed to start mqtt: {:?}", e);
+        None
+    });
+
+    let _httpd = web_server(tx, wifi_manager_api, scheduler_api, outputs_config.clone())?;
 
     led2.set_low()?;
 
     let thr = std::thread::spawn(move || {
         LedStripAnimation::new(
-            pins.gpio6,
-            0,
+            sinks,
+            &outputs_config,
             AnimationConfig {
-                led_quantity: T_CONFIG.led_quantity,
+                led_quantity: outputs_config.total_led_quantity(),
                 ..Default::default()
             },
         )
         .unwrap()
-        .led_strip_loop(rx, applied_config_tx)
+        .led_strip_loop(rx)
         .unwrap()
     });
 
     wifi_manager_thread.join().unwrap();
+    scheduler_thread.join().unwrap();
+    realtime_thread.join().unwrap();
     thr.join().unwrap();
 
     Ok(())
 }
 
+#[cfg(feature = "esp")]
+fn rmt_sinks(outputs_config: &OutputsConfig) -> Result<Vec<RmtSink>> {
+    outputs_config.outputs.iter().map(RmtSink::new).collect()
+}
+
+fn default_outputs_config() -> Result<OutputsConfig> {
+    Ok(OutputsConfig::parse(
+        T_CONFIG.led_outputs,
+        T_CONFIG.led_quantity,
+    )?)
+}
+
+/// Stored led outputs override ones from config
+fn load_outputs_config() -> Result<OutputsConfig> {
+    let default_outputs = default_outputs_config()?;
+
+    Ok(match OutputsConfig::load() {
+        // Stored outputs may be written by older firmware with less strict validation
+        Ok(Some(outputs_config)) => match outputs_config.validate() {
+            Ok(()) => outputs_config,
+            Err(e) => {
+                error!("Stored led outputs are invalid, using default ones: {}", e);
+                default_outputs
+            }
+        },
+        Ok(None) => default_outputs,
+        Err(e) => {
+            error!("Failed to load led outputs: {:?}", e);
+            default_outputs
+        }
+    })
+}
+
+/// Programs stored by firmware without program containers stay playable
+fn migrate_prog_library() {
+    if let Err(e) = prog_library::migrate_legacy() {
+        error!("Failed to migrate program library: {:?}", e);
+    }
+}
+
+fn load_realtime_config() -> RealtimeConfig {
+    match RealtimeConfig::load() {
+        Ok(Some(realtime_config)) => realtime_config,
+        Ok(None) => Default::default(),
+        Err(e) => {
+            error!("Failed to load realtime config: {:?}", e);
+            Default::default()
+        }
+    }
+}
+
+fn load_mqtt_config() -> MqttConfig {
+    match MqttConfig::load() {
+        Ok(Some(mqtt_config)) => mqtt_config,
+        Ok(None) => Default::default(),
+        Err(e) => {
+            error!("Failed to load mqtt config: {:?}", e);
+            Default::default()
+        }
+    }
+}
+
 trait IntoLogLevel {
     fn parse_loglevel(&self) -> Result<log::LevelFilter>;
 }
@@ -129,6 +266,7 @@ impl IntoLogLevel for str {
     }
 }
 
+#[cfg(feature = "esp")]
 fn init_mdns() -> Result<()> {
     let cstr_mdns_hostname = CString::new(T_CONFIG.mdns_hostname)?;
     let cstr_mdns_instance_name = CString::new(T_CONFIG.mdns_instance_name)?;
@@ -1,11 +1,12 @@
-use esp_idf_sys::{esp_fill_random, esp_random};
 use rand::RngCore;
 
+/// Hardware random number generator, on host build backed by thread rng
 pub struct EspRand {}
 
+#[cfg(feature = "esp")]
 impl RngCore for EspRand {
     fn next_u32(&mut self) -> u32 {
-        unsafe { esp_random() }
+        unsafe { esp_idf_sys::esp_random() }
     }
 
     fn next_u64(&mut self) -> u64 {
@@ -13,7 +14,9 @@ impl RngCore for EspRand {
     }
 
     fn fill_bytes(&mut self, dest: &mut [u8]) {
-        unsafe { esp_fill_random(dest.as_mut_ptr() as *mut std::ffi::c_void, dest.len()) }
+        unsafe {
+            esp_idf_sys::esp_fill_random(dest.as_mut_ptr() as *mut std::ffi::c_void, dest.len())
+        }
     }
 
     fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
@@ -21,3 +24,22 @@ impl RngCore for EspRand {
         Ok(())
     }
 }
+
+#[cfg(feature = "host")]
+impl RngCore for EspRand {
+    fn next_u32(&mut self) -> u32 {
+        rand::thread_rng().next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand::thread_rng().next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        rand::thread_rng().fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        rand::thread_rng().try_fill_bytes(dest)
+    }
+}
@@ -0,0 +1,380 @@
+use embedded_svc::http::Method;
+use log::error;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::json;
+use std::fmt::Display;
+use std::io::Read;
+use thiserror::Error;
+
+const JSON_CONTENT_TYPE: &str = "application/json";
+/// Limit of body for routes without own one, whole body is buffered in ram
+const DEFAULT_MAX_BODY: usize = 8 * 1024;
+
+/// Errors of http api, every error has stable code, which clients can rely on
+#[derive(Error, Debug)]
+pub enum ApiError {
+    #[error("{0}")]
+    InvalidJson(#[from] serde_json::Error),
+    #[error("{0}")]
+    InvalidQuery(#[from] serde_urlencoded::de::Error),
+    #[error("{0}")]
+    InvalidBase64(#[from] base64::DecodeError),
+    /// Request is well formed, but its values are not accepted
+    #[error("{0}")]
+    InvalidValue(String),
+    /// Program is rejected before it is run
+    #[error("{0}")]
+    InvalidProgram(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("Body should be at most {0} bytes long")]
+    PayloadTooLarge(usize),
+    /// Request can not be fulfilled in current state of device
+    #[error("{0}")]
+    Rejected(String),
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl ApiError {
+    pub fn invalid_value(e: impl Display) -> Self {
+        Self::InvalidValue(e.to_string())
+    }
+
+    pub fn invalid_program(e: impl Display) -> Self {
+        Self::InvalidProgram(e.to_string())
+    }
+
+    pub fn not_found(e: impl Display) -> Self {
+        Self::NotFound(e.to_string())
+    }
+
+    pub fn rejected(e: impl Display) -> Self {
+        Self::Rejected(e.to_string())
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidJson(_) => "invalid_json",
+            Self::InvalidQuery(_) => "invalid_query",
+            Self::InvalidBase64(_) => "invalid_base64",
+            Self::InvalidValue(_) => "invalid_value",
+            Self::InvalidProgram(_) => "invalid_program",
+            Self::NotFound(_) => "not_found",
+            Self::PayloadTooLarge(_) => "payload_too_large",
+            Self::Rejected(_) => "rejected",
+            Self::Internal(_) => "internal",
+        }
+    }
+
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::InvalidJson(_)
+            | Self::InvalidQuery(_)
+            | Self::InvalidBase64(_)
+            | Self::InvalidValue(_)
+            | Self::InvalidProgram(_) => 400,
+            Self::NotFound(_) => 404,
+            Self::PayloadTooLarge(_) => 413,
+            Self::Rejected(_) => 409,
+            Self::Internal(_) => 500,
+        }
+    }
+}
+
+/// Request with whole body already received, independent of http server implementation
+pub struct ApiRequest {
+    pub method: Method,
+    pub uri: String,
+    pub body: Vec<u8>,
+}
+
+impl ApiRequest {
+    pub fn path(&self) -> &str {
+        self.uri.split('?').next().unwrap_or_default()
+    }
+
+    pub fn query(&self) -> &str {
+        self.uri
+            .split_once('?')
+            .map(|(_, query)| query)
+            .unwrap_or_default()
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, ApiError> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+
+    pub fn query_params<T: DeserializeOwned>(&self) -> Result<T, ApiError> {
+        Ok(serde_urlencoded::from_str(self.query())?)
+    }
+}
+
+/// Api responses are json envelopes, `{"ok": true, "data": ...}` on success
+/// and `{"ok": false, "error": {"code": ..., "message": ...}}` on failure
+pub struct ApiResponse {
+    pub status: u16,
+    pub content_type: Option<&'static str>,
+    pub body: Vec<u8>,
+}
+
+impl ApiResponse {
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            content_type: None,
+            body: Vec::new(),
+        }
+    }
+
+    /// Success without data
+    pub fn ok() -> Self {
+        Self::envelope(200, &json!({"ok": true, "data": null}))
+    }
+
+    pub fn json(data: &impl Serialize) -> anyhow::Result<Self> {
+        Ok(Self::envelope(
+            200,
+            &json!({"ok": true, "data": serde_json::to_value(data)?}),
+        ))
+    }
+
+    fn envelope(status: u16, value: &serde_json::Value) -> Self {
+        Self::new(status)
+            .with_content_type(JSON_CONTENT_TYPE)
+            .with_body(value.to_string())
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn with_content_type(mut self, content_type: &'static str) -> Self {
+        self.content_type = Some(content_type);
+        self
+    }
+}
+
+impl From<ApiError> for ApiResponse {
+    fn from(e: ApiError) -> Self {
+        Self::envelope(
+            e.status(),
+            &json!({"ok": false, "error": {"code": e.code(), "message": e.to_string()}}),
+        )
+    }
+}
+
+type Handler = Box<dyn Fn(&ApiRequest) -> anyhow::Result<ApiResponse> + Send>;
+
+// Body is read straight into buffer of announced length, bodies over limit are rejected without reading them
+fn read_body(
+    reader: impl Read,
+    content_len: Option<u64>,
+    max_body: usize,
+) -> Result<Vec<u8>, ApiError> {
+    let mut body = match content_len {
+        Some(len) if len > max_body as u64 => return Err(ApiError::PayloadTooLarge(max_body)),
+        // Content length is announced by client, so it is not trusted beyond limit
+        Some(len) => Vec::with_capacity(len.min(max_body as u64) as usize),
+        None => Vec::new(),
+    };
+
+    // Body without content length is read until limit is exceeded
+    reader
+        .take((max_body as u64).saturating_add(1))
+        .read_to_end(&mut body)
+        .map_err(|e| ApiError::Internal(format!("Failed to read request body: {}", e)))?;
+    if body.len() > max_body {
+        return Err(ApiError::PayloadTooLarge(max_body));
+    }
+
+    Ok(body)
+}
+
+// Handlers fail with `ApiError` on bad requests, any other error is internal one
+fn call_handler(handler: &Handler, req: &ApiRequest) -> ApiResponse {
+    handler(req).unwrap_or_else(|e| match e.downcast::<ApiError>() {
+        Ok(e) => e.into(),
+        Err(e) => {
+            error!("Failed to handle {}: {:?}", req.uri, e);
+            ApiError::Internal(format!("{:#}", e)).into()
+        }
+    })
+}
+
+struct Route {
+    uri: String,
+    method: Method,
+    max_body: usize,
+    handler: Handler,
+}
+
+#[cfg(feature = "host")]
+impl Route {
+    // Mirrors esp http server wildcard matching, trailing `*` matches any suffix
+    fn matches(&self, method: Method, path: &str) -> bool {
+        self.method == method
+            && match self.uri.strip_suffix('*') {
+                Some(prefix) => path.starts_with(prefix),
+                None => self.uri == path,
+            }
+    }
+}
+
+/// Routes of web server, which can be served by esp http server or by host http server
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Every route takes own uri handler slot of esp-idf server
+    #[cfg(feature = "esp")]
+    pub fn route_count(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn route<F>(&mut self, uri: &str, method: Method, handler: F) -> &mut Self
+    where
+        F: Fn(&ApiRequest) -> anyhow::Result<ApiResponse> + Send + 'static,
+    {
+        self.route_limited(uri, method, DEFAULT_MAX_BODY, handler)
+    }
+
+    /// Route, which rejects bodies longer than `max_body` bytes
+    pub fn route_limited<F>(
+        &mut self,
+        uri: &str,
+        method: Method,
+        max_body: usize,
+        handler: F,
+    ) -> &mut Self
+    where
+        F: Fn(&ApiRequest) -> anyhow::Result<ApiResponse> + Send + 'static,
+    {
+        self.routes.push(Route {
+            uri: uri.to_string(),
+            method,
+            max_body,
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Dispatches request to first matching route, body is read only after route is found
+    #[cfg(feature = "host")]
+    fn handle(
+        &self,
+        method: Method,
+        uri: String,
+        body: impl Read,
+        content_len: Option<u64>,
+    ) -> ApiResponse {
+        let path = uri.split('?').next().unwrap_or_default();
+        let route = match self.routes.iter().find(|route| route.matches(method, path)) {
+            Some(route) => route,
+            None => return ApiError::not_found(format!("No route for {}", path)).into(),
+        };
+
+        match read_body(body, content_len, route.max_body) {
+            Ok(body) => call_handler(&route.handler, &ApiRequest { method, uri, body }),
+            Err(e) => e.into(),
+        }
+    }
+
+    /// Registers routes into `free_handlers` uri handler slots, left in server
+    #[cfg(feature = "esp")]
+    pub fn register(
+        self,
+        server: &mut esp_idf_svc::http::server::EspHttpServer,
+        free_handlers: usize,
+    ) -> anyhow::Result<()> {
+        use embedded_svc::http::Headers;
+        use embedded_svc::io::adapters::ToStd;
+        use embedded_svc::io::Write;
+
+        debug_assert!(
+            self.routes.len() <= free_handlers,
+            "{} routes do not fit into {} uri handlers",
+            self.routes.len(),
+            free_handlers
+        );
+
+        for Route {
+            uri,
+            method,
+            max_body,
+            handler,
+        } in self.routes
+        {
+            server.fn_handler(&uri, method, move |mut req| {
+                let content_len = req.content_len();
+                let response = match read_body(ToStd::new(&mut req), content_len, max_body) {
+                    Ok(body) => call_handler(
+                        &handler,
+                        &ApiRequest {
+                            method,
+                            uri: req.uri().to_string(),
+                            body,
+                        },
+                    ),
+                    Err(e) => e.into(),
+                };
+
+                let content_type = response.content_type.map(|ct| ("Content-Type", ct));
+                req.into_response(response.status, None, content_type.as_slice())?
+                    .write_all(&response.body)?;
+
+                Ok(())
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Serves routes on host, requests are handled one by one like esp http server does
+    #[cfg(feature = "host")]
+    pub fn serve(self, addr: &str) -> anyhow::Result<()> {
+        use anyhow::anyhow;
+
+        let server = tiny_http::Server::http(addr).map_err(|e| anyhow!("{}", e))?;
+        log::info!("Serving http on {}", addr);
+
+        for mut request in server.incoming_requests() {
+            let method = match request.method() {
+                tiny_http::Method::Get => Method::Get,
+                tiny_http::Method::Post => Method::Post,
+                tiny_http::Method::Put => Method::Put,
+                tiny_http::Method::Delete => Method::Delete,
+                _ => {
+                    request.respond(tiny_http::Response::empty(tiny_http::StatusCode(405)))?;
+                    continue;
+                }
+            };
+
+            let uri = request.url().to_string();
+            let content_len = request.body_length().map(|len| len as u64);
+            let response = self.handle(method, uri, request.as_reader(), content_len);
+
+            let mut host_response =
+                tiny_http::Response::from_data(response.body).with_status_code(response.status);
+            if let Some(content_type) = response.content_type {
+                host_response = host_response.with_header(
+                    tiny_http::Header::from_bytes("Content-Type", content_type)
+                        .map_err(|_| anyhow!("Invalid content type"))?,
+                );
+            }
+            if let Err(e) = request.respond(host_response) {
+                error!("Failed to send response: {:?}", e);
+            }
+        }
+
+        Ok(())
+    }
+}
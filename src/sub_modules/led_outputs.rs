@@ -0,0 +1,158 @@
+use super::nvs_storage::NvsNamespace;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+const OUTPUTS_NAMESPACE: &str = "led_outputs";
+const OUTPUTS_KEY: &str = "config";
+// ESP32-C3 has only two transmit rmt channels
+pub const MAX_OUTPUTS: usize = 2;
+pub const DEFAULT_LED_PIN: u32 = 6;
+/// Leds of single output, limited by memory for frames
+pub const MAX_OUTPUT_LED_QUANTITY: usize = 1000;
+// ESP32-C3 has gpio 0-21
+const MAX_PIN: u32 = 21;
+// Status leds of board (12, 13), spi flash (14-17) and usb serial (18, 19)
+const RESERVED_PINS: [u32; 8] = [12, 13, 14, 15, 16, 17, 18, 19];
+
+/// Physical led strip, attached to gpio and driven by rmt channel
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LedOutput {
+    pub pin: u32,
+    pub rmt_channel: u8,
+    pub led_quantity: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputsMode {
+    /// Outputs form one logical strip, in order they are listed
+    #[default]
+    Concatenated,
+    /// Every output is separate segment, running its own program
+    Independent,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputsConfig {
+    pub outputs: Vec<LedOutput>,
+    #[serde(default)]
+    pub mode: OutputsMode,
+}
+
+#[derive(Error, Debug)]
+pub enum OutputsError {
+    #[error("There should be 1-{MAX_OUTPUTS} outputs")]
+    InvalidQuantity,
+    #[error("Output {0}: rmt channel should be less than {MAX_OUTPUTS}")]
+    InvalidChannel(usize),
+    #[error("Output {0}: gpio {1} does not exist or is reserved")]
+    InvalidPin(usize, u32),
+    #[error("Output {0}: led quantity should be in range [1, {MAX_OUTPUT_LED_QUANTITY}]")]
+    InvalidLedQuantity(usize),
+    #[error("Output {0}: rmt channel or pin is already used")]
+    Duplicate(usize),
+    #[error("Failed to parse outputs \"{0}\", expected \"pin:rmt_channel:led_quantity,...\"")]
+    Parse(String),
+}
+
+impl OutputsConfig {
+    /// Parses outputs in form "pin:rmt_channel:led_quantity,...",
+    /// empty string means single output on default pin with `led_quantity` leds
+    pub fn parse(outputs: &str, led_quantity: usize) -> std::result::Result<Self, OutputsError> {
+        if outputs.trim().is_empty() {
+            let config = Self {
+                outputs: vec![LedOutput {
+                    pin: DEFAULT_LED_PIN,
+                    rmt_channel: 0,
+                    led_quantity,
+                }],
+                mode: Default::default(),
+            };
+            config.validate()?;
+
+            return Ok(config);
+        }
+
+        let parse_output = |output: &str| {
+            let mut fields = output.trim().split(':');
+            let mut next_field = || fields.next().map(|field| field.trim());
+
+            match (next_field(), next_field(), next_field(), next_field()) {
+                (Some(pin), Some(rmt_channel), Some(led_quantity), None) => Some(LedOutput {
+                    pin: pin.parse().ok()?,
+                    rmt_channel: rmt_channel.parse().ok()?,
+                    led_quantity: led_quantity.parse().ok()?,
+                }),
+                _ => None,
+            }
+        };
+
+        let config = Self {
+            outputs: outputs
+                .split(',')
+                .map(|output| {
+                    parse_output(output).ok_or_else(|| OutputsError::Parse(output.into()))
+                })
+                .collect::<std::result::Result<_, _>>()?,
+            mode: Default::default(),
+        };
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> std::result::Result<(), OutputsError> {
+        if self.outputs.is_empty() || self.outputs.len() > MAX_OUTPUTS {
+            return Err(OutputsError::InvalidQuantity);
+        }
+
+        for (i, output) in self.outputs.iter().enumerate() {
+            if output.rmt_channel as usize >= MAX_OUTPUTS {
+                return Err(OutputsError::InvalidChannel(i));
+            }
+            if output.pin > MAX_PIN || RESERVED_PINS.contains(&output.pin) {
+                return Err(OutputsError::InvalidPin(i, output.pin));
+            }
+            if !(1..=MAX_OUTPUT_LED_QUANTITY).contains(&output.led_quantity) {
+                return Err(OutputsError::InvalidLedQuantity(i));
+            }
+            let duplicate = self.outputs[..i]
+                .iter()
+                .any(|prev| prev.rmt_channel == output.rmt_channel || prev.pin == output.pin);
+            if duplicate {
+                return Err(OutputsError::Duplicate(i));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn total_led_quantity(&self) -> usize {
+        self.outputs.iter().map(|output| output.led_quantity).sum()
+    }
+
+    /// Stored outputs override compile time ones on boot
+    pub fn load() -> Result<Option<Self>> {
+        let nvs = NvsNamespace::open(OUTPUTS_NAMESPACE)?;
+
+        Ok(match nvs.get_blob(OUTPUTS_KEY)? {
+            Some(blob) => Some(serde_json::from_slice(&blob)?),
+            None => None,
+        })
+    }
+
+    pub fn store(&self) -> Result<()> {
+        NvsNamespace::open(OUTPUTS_NAMESPACE)?.set_blob(OUTPUTS_KEY, &serde_json::to_vec(self)?)?;
+
+        Ok(())
+    }
+
+    pub fn erase() -> Result<()> {
+        NvsNamespace::open(OUTPUTS_NAMESPACE)?.remove(OUTPUTS_KEY)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+use super::strip_type::StripType;
+use anyhow::Result;
+use ws2812_esp32_rmt_driver::RGBW8;
+
+/// Destination of frames produced by animation loop, every led output has its own sink
+pub trait LedSink {
+    /// Shows pixels of single output, encoding them according to strip type if needed
+    fn write(&mut self, pixels: &[RGBW8], strip_type: StripType) -> Result<()>;
+}
+
+#[cfg(feature = "esp")]
+pub use rmt::RmtSink;
+
+#[cfg(feature = "esp")]
+mod rmt {
+    use super::LedSink;
+    use crate::sub_modules::led_outputs::{LedOutput, MAX_OUTPUT_LED_QUANTITY};
+    use crate::sub_modules::strip_type::StripType;
+    use anyhow::{anyhow, Result};
+    use ws2812_esp32_rmt_driver::driver::Ws2812Esp32RmtDriver;
+    use ws2812_esp32_rmt_driver::RGBW8;
+
+    /// Led strip attached to gpio and driven by rmt channel
+    pub struct RmtSink {
+        driver: Ws2812Esp32RmtDriver,
+        // Data transmitted to led strip, encoded according to strip type
+        data: Vec<u8>,
+    }
+
+    impl RmtSink {
+        pub fn new(output: &LedOutput) -> Result<Self> {
+            let mut driver = Ws2812Esp32RmtDriver::new(output.rmt_channel, output.pin)
+                .map_err(|e| anyhow!("{:?}", e))?;
+
+            // Black out led strip, zeroes are black for every strip type
+            driver
+                .write(&[0; 4 * MAX_OUTPUT_LED_QUANTITY])
+                .map_err(|e| anyhow!("{:?}", e))?;
+
+            Ok(Self {
+                driver,
+                data: Vec::new(),
+            })
+        }
+    }
+
+    impl LedSink for RmtSink {
+        fn write(&mut self, pixels: &[RGBW8], strip_type: StripType) -> Result<()> {
+            self.data.clear();
+            for &pixel in pixels {
+                strip_type.encode(pixel, &mut self.data);
+            }
+
+            self.driver
+                .write(&self.data)
+                .map_err(|e| anyhow!("{:?}", e))
+        }
+    }
+}
+
+#[cfg(feature = "host")]
+pub use recording::RecordingSink;
+
+#[cfg(feature = "host")]
+mod recording {
+    use super::LedSink;
+    use crate::sub_modules::strip_type::StripType;
+    use anyhow::Result;
+    use std::collections::VecDeque;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use ws2812_esp32_rmt_driver::RGBW8;
+
+    /// Host sink which keeps last frames in memory, clones share recorded frames,
+    /// so one clone can be given to animation loop and other inspected by tests
+    #[derive(Clone)]
+    pub struct RecordingSink {
+        frames: Arc<Mutex<VecDeque<Vec<RGBW8>>>>,
+        capacity: usize,
+        print_ansi: bool,
+    }
+
+    impl RecordingSink {
+        /// Keeps at most `capacity` last frames
+        pub fn new(capacity: usize) -> Self {
+            Self {
+                frames: Default::default(),
+                capacity,
+                print_ansi: false,
+            }
+        }
+
+        /// Additionally prints every frame to terminal as line of colored blocks
+        pub fn with_ansi_output(mut self) -> Self {
+            self.print_ansi = true;
+            self
+        }
+
+        pub fn last_frame(&self) -> Option<Vec<RGBW8>> {
+            self.frames.lock().unwrap().back().cloned()
+        }
+
+        pub fn clear(&self) {
+            self.frames.lock().unwrap().clear();
+        }
+
+        /// Writes recorded frames as binary PPM image, one row per frame.
+        /// White channel is added to color channels, as it would look on strip
+        pub fn write_ppm(&self, w: &mut impl Write) -> std::io::Result<()> {
+            let frames = self.frames.lock().unwrap();
+            let width = frames.iter().map(Vec::len).max().unwrap_or(0);
+
+            write!(w, "P6\n{} {}\n255\n", width, frames.len())?;
+            for frame in frames.iter() {
+                for x in 0..width {
+                    let [r, g, b] = frame
+                        .get(x)
+                        .map(|&pixel| visible_color(pixel))
+                        .unwrap_or([0; 3]);
+                    w.write_all(&[r, g, b])?;
+                }
+            }
+
+            Ok(())
+        }
+    }
+
+    impl LedSink for RecordingSink {
+        fn write(&mut self, pixels: &[RGBW8], strip_type: StripType) -> Result<()> {
+            // Recorded frame contains only channels strip would receive
+            let frame: Vec<RGBW8> = pixels
+                .iter()
+                .map(|&pixel| {
+                    let mut pixel = pixel;
+                    if !strip_type.white_channel {
+                        pixel.a.0 = 0;
+                    }
+                    pixel
+                })
+                .collect();
+
+            if self.print_ansi {
+                println!("{}", ansi_line(&frame));
+            }
+
+            let mut frames = self.frames.lock().unwrap();
+            if frames.len() >= self.capacity {
+                frames.pop_front();
+            }
+            frames.push_back(frame);
+
+            Ok(())
+        }
+    }
+
+    fn visible_color(pixel: RGBW8) -> [u8; 3] {
+        let white = pixel.a.0;
+        [pixel.r, pixel.g, pixel.b].map(|c| c.saturating_add(white))
+    }
+
+    /// Renders frame as line of 24-bit ANSI colored blocks
+    fn ansi_line(pixels: &[RGBW8]) -> String {
+        let mut line = String::with_capacity(pixels.len() * 20 + 4);
+        for &pixel in pixels {
+            let [r, g, b] = visible_color(pixel);
+            line.push_str(&format!("\x1b[38;2;{};{};{}m\u{2588}", r, g, b));
+        }
+        line.push_str("\x1b[0m");
+        line
+    }
+}
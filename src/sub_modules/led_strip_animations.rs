@@ -1,35 +1,166 @@
-use super::esp_random::EspRand;
+use super::led_outputs::{OutputsConfig, OutputsMode};
+use super::led_sink::LedSink;
+use super::nvs_storage::NvsNamespace;
+use super::output_stage::{dim, limit_power, OutputStage, PowerStatus, WhiteMode};
+use super::segments::{
+    self, Segment, SegmentError, SegmentLayout, SegmentState, VmCommand, VmFault,
+};
+use super::strip_type::StripType;
+use super::transitions::{Fade, TransitionKind};
 use animation_lang::program::Program;
-use animation_lang::vm::{VMState, VMStateConfig, VM};
-use anyhow::{anyhow, Result};
-use esp_idf_hal::gpio::OutputPin;
+use anyhow::{ensure, Result};
 use log::{error, info};
 use serde::{Deserialize, Serialize};
-use smart_leds_trait::{SmartLedsWrite, White};
+use smart_leds_trait::White;
+use std::collections::VecDeque;
 use std::sync::mpsc::Receiver;
 use std::sync::mpsc::{SyncSender, TryRecvError};
-use std::time::Duration;
-use ws2812_esp32_rmt_driver::driver::color::LedPixelColorGrbw32;
-use ws2812_esp32_rmt_driver::{LedPixelEsp32Rmt, RGB8, RGBW8};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use ws2812_esp32_rmt_driver::{RGB8, RGBW8};
 
-type Ws2812I = LedPixelEsp32Rmt<RGBW8, LedPixelColorGrbw32>;
+pub static LOOP_OFF_PROG: &[u8] = binary_macros::base64!("4FAPACARAYEQ4wFxQAEAAeRAAAA=");
 
-static LOOP_OFF_PROG: &[u8] = binary_macros::base64!("4FAPACARAYEQ4wFxQAEAAeRAAAA=");
+pub mod saved_prog {
+    use crate::sub_modules::nvs_storage::{NvsNamespace, StorageError};
+    use crate::sub_modules::segments::MAX_SEGMENTS;
+    use animation_lang::program::Program;
 
-#[derive(Debug, Clone, Copy)]
+    const SAVED_PROG_NAMESPACE: &str = "saved_prog";
+
+    fn prog_key(segment: usize) -> String {
+        format!("seg_prog_{}", segment)
+    }
+
+    /// Last program received by segment, it is started on boot instead of off program
+    pub fn load(segment: usize) -> Result<Option<Program>, StorageError> {
+        let nvs = NvsNamespace::open(SAVED_PROG_NAMESPACE)?;
+
+        Ok(nvs.get_blob(&prog_key(segment))?.map(Program::from_binary))
+    }
+
+    pub fn store(segment: usize, prog: &Program) -> Result<(), StorageError> {
+        NvsNamespace::open(SAVED_PROG_NAMESPACE)?.set_blob(&prog_key(segment), prog.code())
+    }
+
+    /// Erases saved programs of all segments
+    pub fn erase() -> Result<(), StorageError> {
+        let mut nvs = NvsNamespace::open(SAVED_PROG_NAMESPACE)?;
+
+        for segment in 0..MAX_SEGMENTS {
+            nvs.remove(&prog_key(segment))?;
+        }
+
+        Ok(())
+    }
+}
+
+const ANIMATION_CONFIG_NAMESPACE: &str = "anim_config";
+const ANIMATION_CONFIG_KEY: &str = "config";
+const POWER_KEY: &str = "power";
+// Oldest faults are dropped, when buffer is full
+const MAX_FAULT_RECORDS: usize = 16;
+// Config is stored once it stops changing for this time, so dragging brightness slider does not wear flash
+const CONFIG_STORE_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct AnimationConfig {
     pub led_quantity: usize,
     pub fps: u8,
     pub white_brightness: u8,
+    /// Master brightness, applied to every program
+    pub brightness: u8,
+    pub gamma: f32,
+    /// Scale of red, green and blue channels
+    pub color_correction: [u8; 3],
+    /// Current drawn by single fully lit channel of one led
+    pub ma_per_channel: u8,
+    /// Power supply limit, zero means unlimited
+    pub power_limit_ma: u32,
+    /// Default transition between programs, can be overridden per upload
+    pub transition: TransitionKind,
+    pub transition_ms: u16,
+    pub strip_type: StripType,
+    pub white_mode: WhiteMode,
+    /// Color temperature of white leds, used to extract white channel from color
+    pub white_temperature_k: u16,
+    /// Rate of frames streamed to live preview, zero disables stream
+    pub stream_fps: u8,
 }
 
-#[derive(Default, PartialEq, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 #[serde(default)]
 #[serde(deny_unknown_fields)]
 pub struct ReceivedAnimationConfig {
     pub led_quantity: Option<usize>,
     pub fps: Option<u8>,
     pub white_brightness: Option<u8>,
+    pub brightness: Option<u8>,
+    pub gamma: Option<f32>,
+    pub color_correction: Option<[u8; 3]>,
+    pub ma_per_channel: Option<u8>,
+    pub power_limit_ma: Option<u32>,
+    pub transition: Option<TransitionKind>,
+    pub transition_ms: Option<u16>,
+    pub strip_type: Option<StripType>,
+    pub white_mode: Option<WhiteMode>,
+    pub white_temperature_k: Option<u16>,
+    pub stream_fps: Option<u8>,
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("led quantity should be in range [1, {0}], limited by led outputs")]
+    InvalidLedQuantity(usize),
+    #[error("fps should be in range [1, 255]")]
+    InvalidFps,
+    #[error("gamma should be in range (0, 10]")]
+    InvalidGamma,
+    #[error("white temperature should be in range [1000, 40000]")]
+    InvalidWhiteTemperature,
+}
+
+impl ReceivedAnimationConfig {
+    /// `max_led_quantity` is total of leds, which outputs can drive
+    pub fn validate(&self, max_led_quantity: usize) -> std::result::Result<(), ConfigError> {
+        if matches!(self.led_quantity, Some(quantity) if !(1..=max_led_quantity).contains(&quantity))
+        {
+            return Err(ConfigError::InvalidLedQuantity(max_led_quantity));
+        }
+        if self.fps == Some(0) {
+            return Err(ConfigError::InvalidFps);
+        }
+        if matches!(self.gamma, Some(gamma) if !(gamma > 0.0 && gamma <= 10.0)) {
+            return Err(ConfigError::InvalidGamma);
+        }
+        if matches!(self.white_temperature_k, Some(kelvin) if !(1000..=40000).contains(&kelvin)) {
+            return Err(ConfigError::InvalidWhiteTemperature);
+        }
+
+        Ok(())
+    }
+}
+
+impl From<AnimationConfig> for ReceivedAnimationConfig {
+    fn from(config: AnimationConfig) -> Self {
+        Self {
+            led_quantity: Some(config.led_quantity),
+            fps: Some(config.fps),
+            white_brightness: Some(config.white_brightness),
+            brightness: Some(config.brightness),
+            gamma: Some(config.gamma),
+            color_correction: Some(config.color_correction),
+            ma_per_channel: Some(config.ma_per_channel),
+            power_limit_ma: Some(config.power_limit_ma),
+            transition: Some(config.transition),
+            transition_ms: Some(config.transition_ms),
+            strip_type: Some(config.strip_type),
+            white_mode: Some(config.white_mode),
+            white_temperature_k: Some(config.white_temperature_k),
+            stream_fps: Some(config.stream_fps),
+        }
+    }
 }
 
 impl Default for AnimationConfig {
@@ -38,13 +169,119 @@ impl Default for AnimationConfig {
             led_quantity: 150,
             fps: 60,
             white_brightness: 0,
+            brightness: u8::MAX,
+            gamma: 1.0,
+            color_correction: [u8::MAX; 3],
+            ma_per_channel: 20,
+            power_limit_ma: 0,
+            transition: TransitionKind::Cut,
+            transition_ms: 500,
+            strip_type: Default::default(),
+            white_mode: WhiteMode::Fixed,
+            white_temperature_k: 4500,
+            stream_fps: 10,
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct AnimationStatus {
+    /// Whether led strip is turned on
+    pub on: bool,
+    pub power: PowerStatus,
+    /// Pixels streamed over udp are shown instead of programs
+    pub realtime: bool,
+}
+
+/// Everything device is doing, e.g. to initialise forms of frontend
+#[derive(Debug, Clone, Serialize)]
+pub struct AnimationState {
+    pub config: AnimationConfig,
+    pub status: AnimationStatus,
+    pub segments: Vec<SegmentState>,
+    pub uptime_s: u64,
+    /// Rate of frames actually rendered, may be lower than configured fps
+    pub measured_fps: f32,
+}
+
+/// Runtime fault of program, kept in memory until reboot
+#[derive(Debug, Clone, Serialize)]
+pub struct FaultRecord {
+    pub segment: usize,
+    /// Seconds since boot, when program failed
+    pub uptime_s: u64,
+    #[serde(flatten)]
+    pub fault: VmFault,
+}
+
+/// Parameters of program upload, missing transition parameters are taken from config
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct ProgParams {
+    pub transition: Option<TransitionKind>,
+    pub transition_ms: Option<u16>,
+    /// Segment to run program on, `None` means every segment
+    pub segment: Option<usize>,
+    /// Name of program in library, `None` for unnamed uploads
+    #[serde(skip)]
+    pub name: Option<String>,
+    /// Frames per second program is designed for, taken from its container and applied to config
+    #[serde(skip)]
+    pub default_fps: Option<u8>,
+}
+
+/// Parameters of turning led strip on or off
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PowerParams {
+    pub on: bool,
+    /// Duration of fade out or in, zero switches immediately
+    #[serde(default)]
+    pub fade_ms: u16,
+}
+
+/// Receives frames shown on led strip, e.g. live preview in browser
+// Sync, so failed sends of messages can be converted into anyhow errors
+pub trait FrameListener: Send + Sync {
+    /// Frame is sent as `[r, g, b, w]` bytes of every led,
+    /// error means that listener is gone and should be dropped.
+    /// Called by animation thread, so it should not block, e.g. drop frames instead
+    fn send_frame(&mut self, frame: &[u8]) -> Result<()>;
+}
+
 pub enum Messages {
-    NewConfig(ReceivedAnimationConfig),
-    NewProg(Program),
+    NewConfig(ReceivedAnimationConfig, SyncSender<AnimationConfig>),
+    ResetConfig(SyncSender<AnimationConfig>),
+    NewProg(
+        Program,
+        ProgParams,
+        SyncSender<std::result::Result<(), SegmentError>>,
+    ),
+    /// Off blanks led strip and pauses programs, on resumes them
+    SetPower(PowerParams),
+    GetStatus(SyncSender<AnimationStatus>),
+    GetState(SyncSender<AnimationState>),
+    /// Runtime faults of programs, oldest first
+    GetFaults(SyncSender<Vec<FaultRecord>>),
+    ClearFaults,
+    /// Empty layouts mean single segment over whole strip
+    SetSegments(
+        Vec<SegmentLayout>,
+        SyncSender<std::result::Result<(), SegmentError>>,
+    ),
+    GetSegments(SyncSender<Vec<SegmentLayout>>),
+    /// Pauses, resumes or steps program of segment, `None` means every segment
+    ControlVm(
+        VmCommand,
+        Option<usize>,
+        SyncSender<std::result::Result<(), SegmentError>>,
+    ),
+    // Frame stream is served only by esp http server
+    #[cfg_attr(feature = "host", allow(dead_code))]
+    AddFrameListener(Box<dyn FrameListener>),
+    /// Streamed frame, shown instead of programs until timeout passes without new frames
+    RealtimeFrame(Vec<RGB8>, Duration),
 }
 
 impl AnimationConfig {
@@ -58,81 +295,634 @@ impl AnimationConfig {
         if let Some(new_val) = new_config.white_brightness {
             self.white_brightness = new_val;
         }
+        if let Some(new_val) = new_config.brightness {
+            self.brightness = new_val;
+        }
+        if let Some(new_val) = new_config.gamma {
+            self.gamma = new_val;
+        }
+        if let Some(new_val) = new_config.color_correction {
+            self.color_correction = new_val;
+        }
+        if let Some(new_val) = new_config.ma_per_channel {
+            self.ma_per_channel = new_val;
+        }
+        if let Some(new_val) = new_config.power_limit_ma {
+            self.power_limit_ma = new_val;
+        }
+        if let Some(new_val) = new_config.transition {
+            self.transition = new_val;
+        }
+        if let Some(new_val) = new_config.transition_ms {
+            self.transition_ms = new_val;
+        }
+        if let Some(new_val) = new_config.strip_type {
+            self.strip_type = new_val;
+        }
+        if let Some(new_val) = new_config.white_mode {
+            self.white_mode = new_val;
+        }
+        if let Some(new_val) = new_config.white_temperature_k {
+            self.white_temperature_k = new_val;
+        }
+        if let Some(new_val) = new_config.stream_fps {
+            self.stream_fps = new_val;
+        }
+    }
+
+    pub fn validate(&self, max_led_quantity: usize) -> std::result::Result<(), ConfigError> {
+        ReceivedAnimationConfig::from(*self).validate(max_led_quantity)
+    }
+
+    /// Stored config overrides compile time defaults on boot
+    pub fn load() -> Result<Option<Self>> {
+        let nvs = NvsNamespace::open(ANIMATION_CONFIG_NAMESPACE)?;
+
+        Ok(match nvs.get_blob(ANIMATION_CONFIG_KEY)? {
+            Some(blob) => Some(serde_json::from_slice(&blob)?),
+            None => None,
+        })
+    }
+
+    pub fn store(&self) -> Result<()> {
+        NvsNamespace::open(ANIMATION_CONFIG_NAMESPACE)?
+            .set_blob(ANIMATION_CONFIG_KEY, &serde_json::to_vec(self)?)?;
+
+        Ok(())
+    }
+
+    pub fn erase() -> Result<()> {
+        NvsNamespace::open(ANIMATION_CONFIG_NAMESPACE)?.remove(ANIMATION_CONFIG_KEY)?;
+
+        Ok(())
     }
 }
 
-pub struct LedStripAnimation {
-    ws2812: Ws2812I,
-    config: AnimationConfig,
+/// Stored power state is restored on boot, led strip is on by default
+fn load_power() -> Result<Option<bool>> {
+    let nvs = NvsNamespace::open(ANIMATION_CONFIG_NAMESPACE)?;
+
+    Ok(match nvs.get_blob(POWER_KEY)? {
+        Some(blob) => Some(serde_json::from_slice(&blob)?),
+        None => None,
+    })
 }
 
-enum VmStatus {
-    Running(VMState),
-    Stopped((VM, VMStateConfig)),
+fn store_power(on: bool) -> Result<()> {
+    NvsNamespace::open(ANIMATION_CONFIG_NAMESPACE)?
+        .set_blob(POWER_KEY, &serde_json::to_vec(&on)?)?;
+
+    Ok(())
 }
 
-impl LedStripAnimation {
-    pub fn new<P: OutputPin>(led_pin: P, rmt_channel: u8, config: AnimationConfig) -> Result<Self> {
-        let mut ws2812 =
-            LedPixelEsp32Rmt::<RGBW8, LedPixelColorGrbw32>::new(rmt_channel, led_pin.pin() as u32)
-                .map_err(|e| anyhow!("{:?}", e))?;
+// Counts rendered frames over windows of one second
+struct FpsCounter {
+    frames: u32,
+    window_started: Instant,
+    fps: f32,
+}
 
-        // Black out led strip
-        ws2812.write((0..1000).map(|_| RGBW8::new_alpha(0, 0, 0, White(0))))?;
+impl FpsCounter {
+    fn new() -> Self {
+        Self {
+            frames: 0,
+            window_started: Instant::now(),
+            fps: 0.0,
+        }
+    }
+
+    fn frame(&mut self) {
+        self.frames += 1;
+        let elapsed = self.window_started.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.fps = self.frames as f32 / elapsed.as_secs_f32();
+            self.frames = 0;
+            self.window_started = Instant::now();
+        }
+    }
 
-        Ok(Self { ws2812, config })
+    // No frames are rendered while led strip is off, so stale rate is dropped
+    fn fps(&self) -> f32 {
+        if self.window_started.elapsed() >= Duration::from_secs(2) {
+            0.0
+        } else {
+            self.fps
+        }
     }
+}
 
-    pub fn led_strip_loop(
+struct LedStrip<S> {
+    sink: S,
+    led_quantity: usize,
+}
+
+pub struct LedStripAnimation<S: LedSink> {
+    outputs: Vec<LedStrip<S>>,
+    outputs_mode: OutputsMode,
+    config: AnimationConfig,
+    factory_config: AnimationConfig,
+    output_stage: OutputStage,
+    // Custom layouts of segments, `None` means single segment over whole strip
+    segment_layouts: Option<Vec<SegmentLayout>>,
+    segments: Vec<Segment>,
+    // Composed frame of all segments, before output stage
+    shown_frame: Vec<RGB8>,
+    output_frame: Vec<RGBW8>,
+    // Pixels shown by single output
+    output_pixels: Vec<RGBW8>,
+    power_status: PowerStatus,
+    frame_listeners: Vec<Box<dyn FrameListener>>,
+    last_streamed: Instant,
+    // Frame sent to listeners
+    stream_data: Vec<u8>,
+    // Programs are paused until this time, while realtime frames arrive
+    realtime_until: Option<Instant>,
+    // Programs are paused while led strip is off
+    power_on: bool,
+    power_fade: Option<Fade>,
+    started: Instant,
+    fps_counter: FpsCounter,
+    faults: VecDeque<FaultRecord>,
+    // Changed config is stored at this time, unless it changes again
+    store_config_at: Option<Instant>,
+}
+
+impl<S: LedSink> LedStripAnimation<S> {
+    /// Every output in `outputs_config` is shown by corresponding sink
+    pub fn new(
+        sinks: Vec<S>,
+        outputs_config: &OutputsConfig,
+        factory_config: AnimationConfig,
+    ) -> Result<Self> {
+        ensure!(
+            sinks.len() == outputs_config.outputs.len(),
+            "Expected {} led sinks, got {}",
+            outputs_config.outputs.len(),
+            sinks.len()
+        );
+        let outputs = sinks
+            .into_iter()
+            .zip(&outputs_config.outputs)
+            .map(|(sink, output)| LedStrip {
+                sink,
+                led_quantity: output.led_quantity,
+            })
+            .collect();
+
+        // Stored config may be written by older firmware or for other outputs
+        let config = match AnimationConfig::load() {
+            Ok(Some(config)) => match config.validate(outputs_config.total_led_quantity()) {
+                Ok(()) => {
+                    info!("Using stored config: {:?}", config);
+                    config
+                }
+                Err(e) => {
+                    error!("Stored config is invalid, using factory one: {}", e);
+                    factory_config
+                }
+            },
+            Ok(None) => factory_config,
+            Err(e) => {
+                error!("Failed to load stored config: {:?}", e);
+                factory_config
+            }
+        };
+
+        // Stored layouts may not fit into strip, if it was shortened after they were stored
+        let segment_layouts = match segments::load_layouts() {
+            Ok(Some(layouts)) => match segments::validate_layouts(&layouts, config.led_quantity) {
+                Ok(()) => Some(layouts),
+                Err(e) => {
+                    error!(
+                        "Stored segment layouts are invalid, using single segment: {}",
+                        e
+                    );
+                    None
+                }
+            },
+            Ok(None) => None,
+            Err(e) => {
+                error!("Failed to load segment layouts: {:?}", e);
+                None
+            }
+        };
+
+        let power_on = match load_power() {
+            Ok(power_on) => power_on.unwrap_or(true),
+            Err(e) => {
+                error!("Failed to load power state: {:?}", e);
+                true
+            }
+        };
+
+        Ok(Self {
+            outputs,
+            outputs_mode: outputs_config.mode,
+            output_stage: OutputStage::new(&config),
+            segment_layouts,
+            segments: Vec::new(),
+            shown_frame: Vec::with_capacity(config.led_quantity),
+            output_frame: Vec::with_capacity(config.led_quantity),
+            output_pixels: Vec::new(),
+            power_status: Default::default(),
+            frame_listeners: Vec::new(),
+            last_streamed: Instant::now(),
+            stream_data: Vec::new(),
+            realtime_until: None,
+            power_on,
+            power_fade: None,
+            started: Instant::now(),
+            fps_counter: FpsCounter::new(),
+            faults: VecDeque::with_capacity(MAX_FAULT_RECORDS),
+            store_config_at: None,
+            config,
+            factory_config,
+        })
+    }
+
+    // Without custom layouts whole strip is single segment, unless outputs are independent
+    fn follows_strip_length(&self) -> bool {
+        self.segment_layouts.is_none() && self.outputs_mode == OutputsMode::Concatenated
+    }
+
+    fn current_layouts(&self) -> Vec<SegmentLayout> {
+        match &self.segment_layouts {
+            Some(layouts) => layouts.clone(),
+            None => match self.outputs_mode {
+                OutputsMode::Concatenated => vec![SegmentLayout {
+                    start: 0,
+                    length: self.config.led_quantity,
+                    reversed: false,
+                }],
+                OutputsMode::Independent => {
+                    let mut start = 0;
+                    self.outputs
+                        .iter()
+                        .map(|output| {
+                            let layout = SegmentLayout {
+                                start,
+                                length: output.led_quantity,
+                                reversed: false,
+                            };
+                            start += output.led_quantity;
+                            layout
+                        })
+                        .collect()
+                }
+            },
+        }
+    }
+
+    // Every segment starts last program it received, or off program
+    fn build_segments(&mut self) {
+        self.segments = self
+            .current_layouts()
+            .into_iter()
+            .enumerate()
+            .map(|(i, layout)| {
+                let prog = match saved_prog::load(i) {
+                    Ok(Some(prog)) => {
+                        info!("Starting saved program on segment {}", i);
+                        prog
+                    }
+                    Ok(None) => Program::from_binary(LOOP_OFF_PROG.to_vec()),
+                    Err(e) => {
+                        error!("Failed to load saved program: {:?}", e);
+                        Program::from_binary(LOOP_OFF_PROG.to_vec())
+                    }
+                };
+                Segment::new(layout, prog)
+            })
+            .collect();
+    }
+
+    fn apply_config(&mut self) {
+        self.output_stage = OutputStage::new(&self.config);
+
+        // Single segment follows length of strip
+        if self.follows_strip_length() {
+            if let Some(segment) = self.segments.first_mut() {
+                segment.resize(self.config.led_quantity);
+            }
+        }
+    }
+
+    fn set_segments(
+        &mut self,
+        layouts: Vec<SegmentLayout>,
+    ) -> std::result::Result<(), SegmentError> {
+        segments::validate_layouts(&layouts, self.config.led_quantity)?;
+
+        let res = if layouts.is_empty() {
+            self.segment_layouts = None;
+            segments::erase_layouts()
+        } else {
+            let res = segments::store_layouts(&layouts);
+            self.segment_layouts = Some(layouts);
+            res
+        };
+        if let Err(e) = res {
+            error!("Failed to store segment layouts: {:?}", e);
+        }
+
+        self.build_segments();
+
+        Ok(())
+    }
+
+    fn control_vm(
+        &mut self,
+        command: VmCommand,
+        segment: Option<usize>,
+    ) -> std::result::Result<(), SegmentError> {
+        let segments = match segment {
+            Some(i) => {
+                std::slice::from_mut(self.segments.get_mut(i).ok_or(SegmentError::NotFound(i))?)
+            }
+            None => &mut self.segments[..],
+        };
+        // Step would stay pending until programs run again
+        if command == VmCommand::Step && (!self.power_on || self.realtime_until.is_some()) {
+            return Err(SegmentError::NotRunning);
+        }
+
+        info!("Vm command {:?} on segments {:?}", command, segment);
+        for segment in segments {
+            segment.control(command);
+        }
+
+        Ok(())
+    }
+
+    fn status(&self) -> AnimationStatus {
+        AnimationStatus {
+            on: self.power_on,
+            power: self.power_status,
+            realtime: self.realtime_until.is_some(),
+        }
+    }
+
+    fn state(&self) -> AnimationState {
+        AnimationState {
+            config: self.config,
+            status: self.status(),
+            segments: self.segments.iter().map(Segment::state).collect(),
+            uptime_s: self.started.elapsed().as_secs(),
+            measured_fps: self.fps_counter.fps(),
+        }
+    }
+
+    fn set_prog(
         &mut self,
-        rx: Receiver<Messages>,
-        applied_config_tx: SyncSender<AnimationConfig>,
-    ) -> Result<()> {
-        use std::time::Instant;
+        prog: Program,
+        params: ProgParams,
+    ) -> std::result::Result<(), SegmentError> {
+        let transition = params.transition.unwrap_or(self.config.transition);
+        let duration = Duration::from_millis(
+            params
+                .transition_ms
+                .unwrap_or(self.config.transition_ms)
+                .into(),
+        );
+
+        let targets = match params.segment {
+            Some(i) if i >= self.segments.len() => return Err(SegmentError::NotFound(i)),
+            Some(i) => i..i + 1,
+            None => 0..self.segments.len(),
+        };
+
+        // Fps is shared by all segments, same as if it was sent with config
+        if let Some(fps) = params.default_fps {
+            info!("Applying default fps {} of program", fps);
+            if self.config.fps != fps {
+                self.config.fps = fps;
+                self.store_config_later();
+            }
+        }
+
+        for i in targets {
+            if let Err(e) = saved_prog::store(i, &prog) {
+                error!("Failed to save program: {:?}", e);
+            }
+            self.segments[i].set_prog(
+                Program::from_binary(prog.code().to_vec()),
+                params.name.clone(),
+                transition,
+                duration,
+            );
+        }
+
+        Ok(())
+    }
+
+    // Places frames of all segments on strip, later segments overlap earlier ones
+    fn compose_frame(&mut self) {
+        self.shown_frame.clear();
+        self.shown_frame
+            .resize(self.config.led_quantity, RGB8::new(0, 0, 0));
+
+        for segment in &self.segments {
+            let layout = segment.layout();
+            for (i, &c) in segment.shown_frame().iter().take(layout.length).enumerate() {
+                let pos = if layout.reversed {
+                    layout.start + layout.length - 1 - i
+                } else {
+                    layout.start + i
+                };
+                if let Some(pixel) = self.shown_frame.get_mut(pos) {
+                    *pixel = c;
+                }
+            }
+        }
+    }
+
+    // Output level, driven by power state
+    fn power_level(&self) -> u8 {
+        match &self.power_fade {
+            Some(fade) => fade.level(),
+            None if self.power_on => u8::MAX,
+            None => 0,
+        }
+    }
+
+    fn store_config_later(&mut self) {
+        self.store_config_at = Some(Instant::now() + CONFIG_STORE_DELAY);
+    }
+
+    fn store_settled_config(&mut self) {
+        if matches!(self.store_config_at, Some(at) if Instant::now() >= at) {
+            self.store_config_at = None;
+            if let Err(e) = self.config.store() {
+                error!("Failed to store config: {:?}", e);
+            }
+        }
+    }
+
+    fn set_power(&mut self, params: PowerParams) -> Result<()> {
+        if params.on == self.power_on {
+            return Ok(());
+        }
+        info!("Turning led strip {}", if params.on { "on" } else { "off" });
 
+        let from = self.power_level();
+        let to = if params.on { u8::MAX } else { 0 };
+        self.power_fade = Fade::new(from, to, Duration::from_millis(params.fade_ms.into()));
+        self.power_on = params.on;
+        if let Err(e) = store_power(params.on) {
+            error!("Failed to store power state: {:?}", e);
+        }
+
+        self.show_frame()?;
+        self.stream_frame();
+
+        Ok(())
+    }
+
+    fn show_frame(&mut self) -> Result<()> {
+        let output_stage = &self.output_stage;
+        self.output_frame.clear();
+        self.output_frame
+            .extend(self.shown_frame.iter().map(|&c| output_stage.process(c)));
+        dim(&mut self.output_frame, self.power_level());
+        limit_power(
+            &mut self.output_frame,
+            self.config.ma_per_channel,
+            self.config.power_limit_ma,
+            &mut self.power_status,
+        );
+
+        // Logical strip is split between outputs, missing pixels are blacked out
+        let strip_type = self.config.strip_type;
+        let mut pixels = self.output_frame.iter().copied();
+        for output in self.outputs.iter_mut() {
+            self.output_pixels.clear();
+            self.output_pixels.extend(
+                (0..output.led_quantity)
+                    .map(|_| pixels.next().unwrap_or(RGBW8::new_alpha(0, 0, 0, White(0)))),
+            );
+            output.sink.write(&self.output_pixels, strip_type)?;
+        }
+
+        Ok(())
+    }
+
+    // Sends shown frame to listeners, at most `stream_fps` times per second
+    fn stream_frame(&mut self) {
+        if self.frame_listeners.is_empty()
+            || self.config.stream_fps == 0
+            || self.last_streamed.elapsed()
+                < Duration::from_millis(1000 / self.config.stream_fps as u64)
+        {
+            return;
+        }
+        self.last_streamed = Instant::now();
+
+        self.stream_data.clear();
+        for pixel in &self.output_frame {
+            self.stream_data
+                .extend_from_slice(&[pixel.r, pixel.g, pixel.b, pixel.a.0]);
+        }
+
+        let stream_data = &self.stream_data;
+        self.frame_listeners.retain_mut(|listener| {
+            let res = listener.send_frame(stream_data);
+            if let Err(e) = &res {
+                info!("Dropping frame listener: {:?}", e);
+            }
+            res.is_ok()
+        });
+    }
+
+    fn show_realtime_frame(&mut self, pixels: Vec<RGB8>, timeout: Duration) -> Result<()> {
+        // Streams can not turn led strip on
+        if !self.power_on {
+            return Ok(());
+        }
+        if self.realtime_until.is_none() {
+            info!("Realtime stream started");
+        }
+        self.realtime_until = Some(Instant::now() + timeout);
+
+        self.shown_frame.clear();
+        self.shown_frame
+            .extend(pixels.into_iter().take(self.config.led_quantity));
+        self.shown_frame
+            .resize(self.config.led_quantity, RGB8::new(0, 0, 0));
+        self.show_frame()?;
+        self.stream_frame();
+        self.fps_counter.frame();
+
+        Ok(())
+    }
+
+    pub fn led_strip_loop(&mut self, rx: Receiver<Messages>) -> Result<()> {
         let calc_delay = |target_fps| Duration::from_millis(1000 / target_fps as u64);
 
         let mut target_delay = calc_delay(self.config.fps);
         let mut last_update = Instant::now();
         // let mut last_stack_check = Instant::now();
 
-        let mut vm_status =
-            VmStatus::Running(VM::new(self.config.led_quantity, Default::default()).start(
-                Program::from_binary(LOOP_OFF_PROG.to_vec()),
-                VMStateConfig {
-                    local_instruction_limit: Some(1_000_000),
-                    rng: Box::new(EspRand {}),
-                    ..Default::default()
-                },
-            ));
+        self.build_segments();
 
         loop {
             match rx.try_recv() {
                 Ok(message) => match message {
-                    Messages::NewConfig(conf) => {
+                    Messages::NewConfig(conf, applied_config_tx) => {
+                        let previous_config = self.config;
                         self.config.update(conf);
-                        applied_config_tx.send(self.config)?;
+                        if self.config != previous_config {
+                            self.store_config_later();
+                        }
+                        // Sender may not wait for applied config
+                        let _ = applied_config_tx.send(self.config);
                         target_delay = calc_delay(self.config.fps);
-
-                        match vm_status {
-                            VmStatus::Running(vm_state) => {
-                                info!("Restarting vm");
-                                let (mut vm, cfg, prog) = vm_state.stop();
-                                vm.set_stip_length(self.config.led_quantity);
-                                vm_status = VmStatus::Running(vm.start(prog, cfg));
-                            }
-                            VmStatus::Stopped(_) => (),
+                        self.apply_config();
+                    }
+                    Messages::ResetConfig(applied_config_tx) => {
+                        info!("Resetting config to factory defaults");
+                        self.config = self.factory_config;
+                        self.store_config_at = None;
+                        if let Err(e) = AnimationConfig::erase() {
+                            error!("Failed to erase stored config: {:?}", e);
                         }
+                        let _ = applied_config_tx.send(self.config);
+                        target_delay = calc_delay(self.config.fps);
+                        self.apply_config();
                     }
-                    Messages::NewProg(prog) => {
+                    Messages::NewProg(prog, params, res_tx) => {
                         info!("Recieved new program");
-                        vm_status = VmStatus::Running(match vm_status {
-                            VmStatus::Running(vm_state) => {
-                                let (vm, cfg, _) = vm_state.stop();
-                                vm.start(prog, cfg)
-                            }
-                            VmStatus::Stopped((vm, cfg)) => vm.start(prog, cfg),
-                        });
+                        let _ = res_tx.send(self.set_prog(prog, params));
+                        target_delay = calc_delay(self.config.fps);
+                    }
+                    Messages::SetPower(params) => {
+                        self.set_power(params)?;
+                    }
+                    Messages::GetStatus(status_tx) => {
+                        let _ = status_tx.send(self.status());
+                    }
+                    Messages::GetState(state_tx) => {
+                        let _ = state_tx.send(self.state());
+                    }
+                    Messages::GetFaults(faults_tx) => {
+                        let _ = faults_tx.send(self.faults.iter().cloned().collect());
+                    }
+                    Messages::ClearFaults => {
+                        self.faults.clear();
+                    }
+                    Messages::SetSegments(layouts, res_tx) => {
+                        let _ = res_tx.send(self.set_segments(layouts));
+                    }
+                    Messages::GetSegments(layouts_tx) => {
+                        let _ = layouts_tx.send(self.current_layouts());
+                    }
+                    Messages::ControlVm(command, segment, res_tx) => {
+                        let _ = res_tx.send(self.control_vm(command, segment));
+                    }
+                    Messages::AddFrameListener(listener) => {
+                        self.frame_listeners.push(listener);
+                    }
+                    Messages::RealtimeFrame(pixels, timeout) => {
+                        self.show_realtime_frame(pixels, timeout)?;
                     }
                 },
                 Err(TryRecvError::Disconnected) => panic!(),
@@ -151,28 +941,53 @@ impl LedStripAnimation {
             //     last_stack_check = Instant::now();
             // }
 
-            if last_update.elapsed() >= target_delay {
+            self.store_settled_config();
+
+            if matches!(self.realtime_until, Some(until) if Instant::now() >= until) {
+                info!("Realtime stream timed out, resuming programs");
+                self.realtime_until = None;
+                self.compose_frame();
+                self.show_frame()?;
+                self.stream_frame();
+            }
+
+            let fading = self.power_fade.is_some();
+            if (self.power_on || fading)
+                && self.realtime_until.is_none()
+                && last_update.elapsed() >= target_delay
+            {
                 last_update = Instant::now();
-                if let VmStatus::Running(mut vm_state) = vm_status {
-                    vm_status = match vm_state.next() {
-                        None => {
-                            info!("Program ended");
-                            info!("Halting VM and Waiting for new prog...");
-                            let (vm, cfg, _) = vm_state.stop();
-                            VmStatus::Stopped((vm, cfg))
-                        }
-                        Some(Err(e)) => {
-                            error!("{:?}", e);
-                            info!("Halting VM and Waiting for new prog...");
-                            let (vm, cfg, _) = vm_state.stop();
-                            VmStatus::Stopped((vm, cfg))
-                        }
-                        Some(Ok(v)) => {
-                            self.ws2812.write(v.map(|c| {
-                                RGBW8::new_alpha(c.r, c.g, c.b, White(self.config.white_brightness))
-                            }))?;
-                            VmStatus::Running(vm_state)
+
+                let mut frame_updated = false;
+                for (i, segment) in self.segments.iter_mut().enumerate() {
+                    frame_updated |= segment.next_frame();
+                    if let Some(fault) = segment.take_fault() {
+                        if self.faults.len() == MAX_FAULT_RECORDS {
+                            self.faults.pop_front();
                         }
+                        self.faults.push_back(FaultRecord {
+                            segment: i,
+                            uptime_s: self.started.elapsed().as_secs(),
+                            fault,
+                        });
+                    }
+                }
+
+                // Fading frame is shown even if programs do not produce new frames
+                if frame_updated || fading {
+                    self.compose_frame();
+                    self.show_frame()?;
+                    self.stream_frame();
+                    // Ticks of paused or stopped programs do not render frames
+                    self.fps_counter.frame();
+                }
+
+                if matches!(&self.power_fade, Some(fade) if fade.is_finished()) {
+                    self.power_fade = None;
+                    // Last frame of fade out may be not completely black
+                    if !self.power_on {
+                        self.show_frame()?;
+                        self.stream_frame();
                     }
                 }
             }
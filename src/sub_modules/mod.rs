@@ -1,5 +1,18 @@
 pub mod esp_random;
+#[cfg(feature = "esp")]
 pub mod esp_sntp_wrapper;
+pub mod http_router;
+pub mod led_outputs;
+pub mod led_sink;
 pub mod led_strip_animations;
+pub mod mqtt;
+pub mod nvs_storage;
+pub mod output_stage;
+pub mod prog_library;
+pub mod realtime;
+pub mod scheduler;
+pub mod segments;
+pub mod strip_type;
+pub mod transitions;
 pub mod web_server;
 pub mod wifi_manager;
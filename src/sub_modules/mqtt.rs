@@ -0,0 +1,936 @@
+use super::led_strip_animations::{
+    AnimationState, Messages, PowerParams, ProgParams, ReceivedAnimationConfig,
+};
+use super::nvs_storage::NvsNamespace;
+use super::prog_library;
+use super::segments::SegmentState;
+use animation_lang::program::Program;
+use anyhow::Result;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+const MQTT_NAMESPACE: &str = "mqtt";
+const MQTT_KEY: &str = "config";
+
+const AVAILABILITY_TOPIC: &str = "availability";
+const STATE_TOPIC: &str = "state";
+const COMMAND_TOPIC: &str = "set";
+const CONFIG_COMMAND_TOPIC: &str = "config/set";
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+// State and effect list are refreshed periodically, to reflect changes made over http
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct MqttConfig {
+    /// Broker url, e.g. "mqtt://192.168.1.10:1883", empty disables mqtt
+    pub broker_url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Client id, also used as unique id of device in Home Assistant
+    pub client_id: String,
+    /// Prefix of device topics
+    pub base_topic: String,
+    /// Prefix of Home Assistant discovery topics
+    pub discovery_prefix: String,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            broker_url: String::new(),
+            username: None,
+            password: None,
+            client_id: "rust_led_strip".to_string(),
+            base_topic: "rust_led_strip".to_string(),
+            discovery_prefix: "homeassistant".to_string(),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum MqttError {
+    #[error("broker_url should start with \"mqtt://\"")]
+    InvalidBrokerUrl,
+    #[error("client_id should not be empty")]
+    EmptyClientId,
+    #[error("{0} should not be empty or contain wildcards")]
+    InvalidTopic(&'static str),
+}
+
+impl MqttConfig {
+    pub fn enabled(&self) -> bool {
+        !self.broker_url.is_empty()
+    }
+
+    pub fn validate(&self) -> std::result::Result<(), MqttError> {
+        let valid_topic = |topic: &str| !topic.is_empty() && !topic.contains(['+', '#']);
+
+        if self.enabled() && !self.broker_url.starts_with("mqtt://") {
+            return Err(MqttError::InvalidBrokerUrl);
+        }
+        if self.client_id.is_empty() {
+            return Err(MqttError::EmptyClientId);
+        }
+        if !valid_topic(&self.base_topic) {
+            return Err(MqttError::InvalidTopic("base_topic"));
+        }
+        if !valid_topic(&self.discovery_prefix) {
+            return Err(MqttError::InvalidTopic("discovery_prefix"));
+        }
+
+        Ok(())
+    }
+
+    /// Password is not returned to clients, so settings without it keep password of `stored` ones,
+    /// empty password removes it
+    pub fn keep_password(&mut self, stored: Option<MqttConfig>) {
+        self.password = match self.password.take() {
+            Some(password) if password.is_empty() => None,
+            Some(password) => Some(password),
+            None => stored.and_then(|stored| stored.password),
+        };
+    }
+
+    fn topic(&self, suffix: &str) -> String {
+        format!("{}/{}", self.base_topic, suffix)
+    }
+
+    /// Stored config overrides defaults on boot
+    pub fn load() -> Result<Option<Self>> {
+        let nvs = NvsNamespace::open(MQTT_NAMESPACE)?;
+
+        Ok(match nvs.get_blob(MQTT_KEY)? {
+            Some(blob) => Some(serde_json::from_slice(&blob)?),
+            None => None,
+        })
+    }
+
+    pub fn store(&self) -> Result<()> {
+        NvsNamespace::open(MQTT_NAMESPACE)?.set_blob(MQTT_KEY, &serde_json::to_vec(self)?)?;
+
+        Ok(())
+    }
+
+    pub fn erase() -> Result<()> {
+        NvsNamespace::open(MQTT_NAMESPACE)?.remove(MQTT_KEY)?;
+
+        Ok(())
+    }
+}
+
+pub enum MqttEvent {
+    Connected,
+    Disconnected,
+    Message { topic: String, payload: Vec<u8> },
+}
+
+/// Connection to broker, incoming events are delivered to channel given on connect.
+/// Availability topic is set to offline by last will
+pub trait MqttClient: Send {
+    fn subscribe(&mut self, topic: &str) -> Result<()>;
+    fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) -> Result<()>;
+}
+
+#[cfg(feature = "esp")]
+pub mod esp_client {
+    use super::{MqttClient, MqttConfig, MqttEvent, AVAILABILITY_TOPIC, OFFLINE};
+    use anyhow::Result;
+    use embedded_svc::mqtt::client::{Details, Event, Message, QoS};
+    use esp_idf_svc::mqtt::client::{EspMqttClient, LwtConfiguration, MqttClientConfiguration};
+    use log::error;
+    use std::sync::mpsc::Sender;
+
+    pub struct EspMqtt(EspMqttClient);
+
+    impl EspMqtt {
+        pub fn connect(config: &MqttConfig, events_tx: Sender<MqttEvent>) -> Result<Self> {
+            let availability_topic = config.topic(AVAILABILITY_TOPIC);
+
+            let client = EspMqttClient::new(
+                &config.broker_url,
+                &MqttClientConfiguration {
+                    client_id: Some(&config.client_id),
+                    username: config.username.as_deref(),
+                    password: config.password.as_deref(),
+                    lwt: Some(LwtConfiguration {
+                        topic: &availability_topic,
+                        payload: OFFLINE.as_bytes(),
+                        qos: QoS::AtLeastOnce,
+                        retain: true,
+                    }),
+                    ..Default::default()
+                },
+                move |event| {
+                    let event = match event {
+                        Ok(Event::Connected(_)) => MqttEvent::Connected,
+                        Ok(Event::Disconnected) => MqttEvent::Disconnected,
+                        // Commands are small, chunked messages are not expected
+                        Ok(Event::Received(message))
+                            if matches!(message.details(), Details::Complete) =>
+                        {
+                            MqttEvent::Message {
+                                topic: message
+                                    .topic()
+                                    .map(|topic| topic.to_string())
+                                    .unwrap_or_default(),
+                                payload: message.data().to_vec(),
+                            }
+                        }
+                        Ok(_) => return,
+                        Err(e) => {
+                            error!("Mqtt error: {:?}", e);
+                            return;
+                        }
+                    };
+                    let _ = events_tx.send(event);
+                },
+            )?;
+
+            Ok(Self(client))
+        }
+    }
+
+    impl MqttClient for EspMqtt {
+        fn subscribe(&mut self, topic: &str) -> Result<()> {
+            self.0.subscribe(topic, QoS::AtLeastOnce)?;
+
+            Ok(())
+        }
+
+        fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) -> Result<()> {
+            self.0.publish(topic, QoS::AtLeastOnce, retain, payload)?;
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "host")]
+pub mod host_client {
+    use super::{MqttClient, MqttConfig, MqttEvent, AVAILABILITY_TOPIC, OFFLINE};
+    use anyhow::{anyhow, Result};
+    use log::error;
+    use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS};
+    use std::sync::mpsc::Sender;
+    use std::time::Duration;
+
+    const DEFAULT_PORT: u16 = 1883;
+
+    pub struct HostMqtt(Client);
+
+    impl HostMqtt {
+        pub fn connect(config: &MqttConfig, events_tx: Sender<MqttEvent>) -> Result<Self> {
+            let address = config
+                .broker_url
+                .strip_prefix("mqtt://")
+                .ok_or_else(|| anyhow!("Unsupported broker url {}", config.broker_url))?;
+            let (host, port) = match address.rsplit_once(':') {
+                Some((host, port)) => (host, port.parse()?),
+                None => (address, DEFAULT_PORT),
+            };
+
+            let mut options = MqttOptions::new(&config.client_id, host, port);
+            options.set_keep_alive(Duration::from_secs(30));
+            options.set_last_will(LastWill::new(
+                config.topic(AVAILABILITY_TOPIC),
+                OFFLINE,
+                QoS::AtLeastOnce,
+                true,
+            ));
+            if let Some(username) = &config.username {
+                options.set_credentials(username, config.password.clone().unwrap_or_default());
+            }
+
+            let (client, mut connection) = Client::new(options, 16);
+
+            // Connection reconnects on next iteration after error
+            std::thread::spawn(move || {
+                for notification in connection.iter() {
+                    let event = match notification {
+                        Ok(Event::Incoming(Packet::ConnAck(_))) => MqttEvent::Connected,
+                        Ok(Event::Incoming(Packet::Publish(publish))) => MqttEvent::Message {
+                            topic: publish.topic,
+                            payload: publish.payload.to_vec(),
+                        },
+                        Ok(_) => continue,
+                        Err(e) => {
+                            error!("Mqtt error: {:?}", e);
+                            std::thread::sleep(Duration::from_secs(1));
+                            MqttEvent::Disconnected
+                        }
+                    };
+                    if events_tx.send(event).is_err() {
+                        return;
+                    }
+                }
+            });
+
+            Ok(Self(client))
+        }
+    }
+
+    impl MqttClient for HostMqtt {
+        fn subscribe(&mut self, topic: &str) -> Result<()> {
+            self.0.subscribe(topic, QoS::AtLeastOnce)?;
+
+            Ok(())
+        }
+
+        fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) -> Result<()> {
+            self.0.publish(topic, QoS::AtLeastOnce, retain, payload)?;
+
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+enum LightPower {
+    On,
+    Off,
+}
+
+/// Command of Home Assistant light with json schema
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct LightCommand {
+    state: Option<LightPower>,
+    brightness: Option<u8>,
+    effect: Option<String>,
+    /// Fade duration of turning on or off, in seconds
+    transition: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct LightState {
+    state: LightPower,
+    brightness: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    effect: Option<String>,
+}
+
+// Program from library is effect, only if every segment runs it, no matter whether
+// it was played over mqtt, http or by scheduler
+fn effect(segments: &[SegmentState]) -> Option<String> {
+    let name = segments.first()?.program.name.as_ref()?;
+
+    segments
+        .iter()
+        .all(|segment| segment.program.name.as_ref() == Some(name))
+        .then(|| name.clone())
+}
+
+/// Maps mqtt commands onto animation messages and publishes state of led strip,
+/// effects of Home Assistant light are programs stored in library
+pub struct MqttBridge<C: MqttClient> {
+    config: MqttConfig,
+    client: C,
+    events_rx: Receiver<MqttEvent>,
+    tx: SyncSender<Messages>,
+    max_led_quantity: usize,
+    effect_list: Vec<String>,
+    last_state: Option<LightState>,
+    connected: bool,
+}
+
+impl<C: MqttClient + 'static> MqttBridge<C> {
+    pub fn new(
+        config: MqttConfig,
+        client: C,
+        events_rx: Receiver<MqttEvent>,
+        tx: SyncSender<Messages>,
+        max_led_quantity: usize,
+    ) -> Self {
+        Self {
+            config,
+            client,
+            events_rx,
+            tx,
+            max_led_quantity,
+            effect_list: Vec::new(),
+            last_state: None,
+            connected: false,
+        }
+    }
+
+    fn get_state(&self) -> Result<AnimationState> {
+        let (state_tx, state_rx) = mpsc::sync_channel(1);
+        self.tx.send(Messages::GetState(state_tx))?;
+
+        Ok(state_rx.recv()?)
+    }
+
+    fn send_power(&self, on: bool, fade_ms: u16) -> Result<()> {
+        self.tx
+            .send(Messages::SetPower(PowerParams { on, fade_ms }))?;
+
+        Ok(())
+    }
+
+    fn send_config(&self, config: ReceivedAnimationConfig) -> Result<()> {
+        // Nobody waits for applied config, so reply is dropped
+        let (applied_config_tx, _) = mpsc::sync_channel(1);
+        self.tx
+            .send(Messages::NewConfig(config, applied_config_tx))?;
+
+        Ok(())
+    }
+
+    fn play(&self, effect: String) -> Result<()> {
+        let container = prog_library::load_container(&effect)?;
+        let default_fps = container.metadata().default_fps;
+        let (res_tx, res_rx) = mpsc::sync_channel(1);
+        self.tx.send(Messages::NewProg(
+            Program::from_binary(container.into_code()),
+            ProgParams {
+                name: Some(effect),
+                default_fps,
+                ..Default::default()
+            },
+            res_tx,
+        ))?;
+        res_rx.recv()??;
+
+        Ok(())
+    }
+
+    fn publish_discovery(&mut self) -> Result<()> {
+        let discovery = json!({
+            "name": null,
+            "unique_id": format!("{}_light", self.config.client_id),
+            "schema": "json",
+            "command_topic": self.config.topic(COMMAND_TOPIC),
+            "state_topic": self.config.topic(STATE_TOPIC),
+            "availability_topic": self.config.topic(AVAILABILITY_TOPIC),
+            "brightness": true,
+            "brightness_scale": 255,
+            "effect": true,
+            "effect_list": self.effect_list,
+            "device": {
+                "identifiers": [self.config.client_id],
+                "name": self.config.client_id,
+                "model": "Rust led strip",
+                "sw_version": env!("CARGO_PKG_VERSION"),
+            },
+        });
+        let topic = format!(
+            "{}/light/{}/config",
+            self.config.discovery_prefix, self.config.client_id
+        );
+
+        self.client
+            .publish(&topic, &serde_json::to_vec(&discovery)?, true)
+    }
+
+    // State is published only when it changes
+    fn publish_state(&mut self) -> Result<()> {
+        let animation_state = self.get_state()?;
+        let state = LightState {
+            state: if animation_state.status.on {
+                LightPower::On
+            } else {
+                LightPower::Off
+            },
+            brightness: animation_state.config.brightness,
+            effect: effect(&animation_state.segments),
+        };
+        if self.last_state.as_ref() == Some(&state) {
+            return Ok(());
+        }
+
+        self.client.publish(
+            &self.config.topic(STATE_TOPIC),
+            &serde_json::to_vec(&state)?,
+            true,
+        )?;
+        self.last_state = Some(state);
+
+        Ok(())
+    }
+
+    fn on_connected(&mut self) -> Result<()> {
+        info!("Connected to mqtt broker {}", self.config.broker_url);
+        self.connected = true;
+
+        self.client.subscribe(&self.config.topic(COMMAND_TOPIC))?;
+        self.client
+            .subscribe(&self.config.topic(CONFIG_COMMAND_TOPIC))?;
+        self.client.publish(
+            &self.config.topic(AVAILABILITY_TOPIC),
+            ONLINE.as_bytes(),
+            true,
+        )?;
+
+        self.effect_list = prog_library::list()?;
+        self.publish_discovery()?;
+        self.last_state = None;
+        self.publish_state()
+    }
+
+    // Effect list follows program library
+    fn refresh(&mut self) -> Result<()> {
+        let effect_list = prog_library::list()?;
+        if effect_list != self.effect_list {
+            self.effect_list = effect_list;
+            self.publish_discovery()?;
+        }
+
+        self.publish_state()
+    }
+
+    fn handle_command(&mut self, command: LightCommand) -> Result<()> {
+        let fade_ms = command
+            .transition
+            .map(|seconds| (seconds * 1000.0).clamp(0.0, u16::MAX as f32) as u16)
+            .unwrap_or_default();
+
+        if command.state == Some(LightPower::Off) {
+            self.send_power(false, fade_ms)?;
+            return self.publish_state();
+        }
+
+        if let Some(brightness) = command.brightness {
+            self.send_config(ReceivedAnimationConfig {
+                brightness: Some(brightness),
+                ..Default::default()
+            })?;
+        }
+        if let Some(effect) = command.effect {
+            self.play(effect)?;
+        }
+        // Home Assistant sends brightness and effect only to turn light on
+        self.send_power(true, fade_ms)?;
+
+        self.publish_state()
+    }
+
+    fn on_message(&mut self, topic: &str, payload: &[u8]) -> Result<()> {
+        if topic == self.config.topic(COMMAND_TOPIC) {
+            self.handle_command(serde_json::from_slice(payload)?)
+        } else if topic == self.config.topic(CONFIG_COMMAND_TOPIC) {
+            let config: ReceivedAnimationConfig = serde_json::from_slice(payload)?;
+            config.validate(self.max_led_quantity)?;
+            self.send_config(config)?;
+            self.publish_state()
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn daemon(self, stack_size: usize) -> Result<JoinHandle<()>> {
+        Ok(std::thread::Builder::new()
+            .stack_size(stack_size)
+            .spawn(move || {
+                let mut bridge = self;
+                let mut last_refresh = Instant::now();
+                loop {
+                    let res = match bridge.events_rx.recv_timeout(REFRESH_INTERVAL) {
+                        Ok(MqttEvent::Connected) => bridge.on_connected(),
+                        Ok(MqttEvent::Disconnected) => {
+                            if bridge.connected {
+                                info!("Disconnected from mqtt broker");
+                            }
+                            bridge.connected = false;
+                            Ok(())
+                        }
+                        Ok(MqttEvent::Message { topic, payload }) => {
+                            bridge.on_message(&topic, &payload)
+                        }
+                        Err(RecvTimeoutError::Timeout) => Ok(()),
+                        Err(RecvTimeoutError::Disconnected) => {
+                            error!("Mqtt client is gone, stopping bridge");
+                            return;
+                        }
+                    };
+                    if let Err(e) = res {
+                        error!("Mqtt bridge error: {:?}", e);
+                    }
+
+                    if bridge.connected && last_refresh.elapsed() >= REFRESH_INTERVAL {
+                        last_refresh = Instant::now();
+                        if let Err(e) = bridge.refresh() {
+                            error!("Failed to refresh mqtt state: {:?}", e);
+                        }
+                    }
+                }
+            })?)
+    }
+}
+
+/// Connects to broker and runs bridge in background, if broker is configured
+pub fn start(
+    config: MqttConfig,
+    tx: SyncSender<Messages>,
+    max_led_quantity: usize,
+    stack_size: usize,
+) -> Result<Option<JoinHandle<()>>> {
+    if !config.enabled() {
+        info!("Mqtt is disabled");
+        return Ok(None);
+    }
+
+    let (events_tx, events_rx) = mpsc::channel();
+    #[cfg(feature = "esp")]
+    let client = esp_client::EspMqtt::connect(&config, events_tx)?;
+    #[cfg(feature = "host")]
+    let client = host_client::HostMqtt::connect(&config, events_tx)?;
+
+    MqttBridge::new(config, client, events_rx, tx, max_led_quantity)
+        .daemon(stack_size)
+        .map(Some)
+}
+
+#[cfg(all(test, feature = "host"))]
+mod tests {
+    use super::*;
+    use crate::sub_modules::led_strip_animations::{
+        AnimationConfig, AnimationStatus, LOOP_OFF_PROG,
+    };
+    use crate::sub_modules::segments::{ProgramInfo, SegmentLayout, VmState};
+    use prog_container::{Container, Metadata};
+    use serde_json::Value;
+    use std::sync::{Arc, Mutex};
+
+    const EFFECT: &str = "mqtt_off";
+
+    #[derive(Clone, Default)]
+    struct FakeClient {
+        subscribed: Arc<Mutex<Vec<String>>>,
+        published: Arc<Mutex<Vec<(String, Vec<u8>, bool)>>>,
+    }
+
+    impl FakeClient {
+        // Payloads published to topic since last call, retained flag is asserted
+        fn take_published(&self, topic: &str) -> Vec<Value> {
+            let mut published = self.published.lock().unwrap();
+            let (taken, rest): (Vec<_>, Vec<_>) =
+                published.drain(..).partition(|(t, _, _)| t == topic);
+            *published = rest;
+
+            taken
+                .into_iter()
+                .map(|(_, payload, retain)| {
+                    assert!(retain, "{} should be retained", topic);
+                    serde_json::from_slice(&payload)
+                        .unwrap_or_else(|_| Value::String(String::from_utf8(payload).unwrap()))
+                })
+                .collect()
+        }
+    }
+
+    impl MqttClient for FakeClient {
+        fn subscribe(&mut self, topic: &str) -> Result<()> {
+            self.subscribed.lock().unwrap().push(topic.to_string());
+
+            Ok(())
+        }
+
+        fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) -> Result<()> {
+            self.published
+                .lock()
+                .unwrap()
+                .push((topic.to_string(), payload.to_vec(), retain));
+
+            Ok(())
+        }
+    }
+
+    // Messages received by animation thread, which change led strip
+    #[derive(Debug, PartialEq)]
+    enum Command {
+        Config(ReceivedAnimationConfig),
+        Prog(Option<String>),
+        Power(PowerParams),
+    }
+
+    // Stands in for animation thread, keeps state and records commands
+    #[derive(Clone)]
+    struct FakeAnimation {
+        state: Arc<Mutex<AnimationState>>,
+        commands: Arc<Mutex<Vec<Command>>>,
+    }
+
+    impl FakeAnimation {
+        fn spawn(rx: Receiver<Messages>) -> Self {
+            let animation = Self {
+                state: Arc::new(Mutex::new(AnimationState {
+                    config: AnimationConfig::default(),
+                    status: AnimationStatus {
+                        on: true,
+                        power: Default::default(),
+                        realtime: false,
+                    },
+                    segments: vec![segment(0, None)],
+                    uptime_s: 0,
+                    measured_fps: 0.0,
+                })),
+                commands: Default::default(),
+            };
+
+            let fake = animation.clone();
+            std::thread::spawn(move || {
+                for message in rx {
+                    fake.handle(message);
+                }
+            });
+
+            animation
+        }
+
+        fn handle(&self, message: Messages) {
+            let mut state = self.state.lock().unwrap();
+            let mut commands = self.commands.lock().unwrap();
+            match message {
+                Messages::NewConfig(config, applied_config_tx) => {
+                    state.config.update(config.clone());
+                    commands.push(Command::Config(config));
+                    let _ = applied_config_tx.send(state.config);
+                }
+                Messages::NewProg(_, params, res_tx) => {
+                    set_program(&mut state, params.name.clone());
+                    commands.push(Command::Prog(params.name));
+                    let _ = res_tx.send(Ok(()));
+                }
+                Messages::SetPower(params) => {
+                    state.status.on = params.on;
+                    commands.push(Command::Power(params));
+                }
+                Messages::GetState(state_tx) => {
+                    let _ = state_tx.send(state.clone());
+                }
+                _ => panic!("Unexpected message"),
+            }
+        }
+
+        fn take_commands(&self) -> Vec<Command> {
+            std::mem::take(&mut *self.commands.lock().unwrap())
+        }
+    }
+
+    fn set_program(state: &mut AnimationState, name: Option<String>) {
+        for segment in state.segments.iter_mut() {
+            segment.program.name = name.clone();
+        }
+    }
+
+    fn segment(start: usize, name: Option<&str>) -> SegmentState {
+        SegmentState {
+            layout: SegmentLayout {
+                start,
+                length: 10,
+                reversed: false,
+            },
+            vm: VmState::Running,
+            program: ProgramInfo {
+                hash: String::new(),
+                name: name.map(str::to_string),
+            },
+            last_error: None,
+        }
+    }
+
+    fn start_bridge() -> (MqttBridge<FakeClient>, FakeClient, FakeAnimation) {
+        // Tests share in-memory storage, so every test stores same effect
+        let container = Container::new(
+            Metadata {
+                name: EFFECT.to_string(),
+                ..Default::default()
+            },
+            LOOP_OFF_PROG.to_vec(),
+        )
+        .unwrap();
+        prog_library::store(EFFECT, &container.encode()).unwrap();
+
+        let client = FakeClient::default();
+        let (tx, rx) = mpsc::sync_channel(0);
+        let animation = FakeAnimation::spawn(rx);
+        let (_, events_rx) = mpsc::channel();
+        let bridge = MqttBridge::new(
+            MqttConfig {
+                broker_url: "mqtt://localhost".to_string(),
+                ..Default::default()
+            },
+            client.clone(),
+            events_rx,
+            tx,
+            150,
+        );
+
+        (bridge, client, animation)
+    }
+
+    #[test]
+    fn light_commands_are_mapped_to_messages() {
+        let (mut bridge, _, animation) = start_bridge();
+
+        bridge
+            .on_message(
+                "rust_led_strip/set",
+                br#"{"state": "ON", "brightness": 100, "effect": "mqtt_off", "transition": 0.5}"#,
+            )
+            .unwrap();
+        assert_eq!(
+            animation.take_commands(),
+            [
+                Command::Config(ReceivedAnimationConfig {
+                    brightness: Some(100),
+                    ..Default::default()
+                }),
+                Command::Prog(Some(EFFECT.to_string())),
+                Command::Power(PowerParams {
+                    on: true,
+                    fade_ms: 500
+                }),
+            ]
+        );
+
+        bridge
+            .on_message(
+                "rust_led_strip/set",
+                br#"{"state": "OFF", "brightness": 10, "transition": 2}"#,
+            )
+            .unwrap();
+        assert_eq!(
+            animation.take_commands(),
+            [Command::Power(PowerParams {
+                on: false,
+                fade_ms: 2000
+            })]
+        );
+    }
+
+    #[test]
+    fn config_commands_are_validated() {
+        let (mut bridge, _, animation) = start_bridge();
+
+        bridge
+            .on_message("rust_led_strip/config/set", br#"{"fps": 30}"#)
+            .unwrap();
+        assert_eq!(
+            animation.take_commands(),
+            [Command::Config(ReceivedAnimationConfig {
+                fps: Some(30),
+                ..Default::default()
+            })]
+        );
+
+        assert!(bridge
+            .on_message("rust_led_strip/config/set", br#"{"led_quantity": 151}"#)
+            .is_err());
+        assert!(bridge
+            .on_message("rust_led_strip/set", br#"{"effect": "missing"}"#)
+            .is_err());
+        assert!(animation.take_commands().is_empty());
+    }
+
+    #[test]
+    fn discovery_is_published_on_connect() {
+        let (mut bridge, client, _) = start_bridge();
+
+        bridge.on_connected().unwrap();
+
+        assert_eq!(
+            *client.subscribed.lock().unwrap(),
+            ["rust_led_strip/set", "rust_led_strip/config/set"]
+        );
+        assert_eq!(
+            client.take_published("rust_led_strip/availability"),
+            [Value::from("online")]
+        );
+        let discovery = client.take_published("homeassistant/light/rust_led_strip/config");
+        assert_eq!(discovery.len(), 1);
+        let discovery = &discovery[0];
+        assert_eq!(discovery["unique_id"], "rust_led_strip_light");
+        assert_eq!(discovery["schema"], "json");
+        assert_eq!(discovery["command_topic"], "rust_led_strip/set");
+        assert_eq!(discovery["state_topic"], "rust_led_strip/state");
+        assert_eq!(
+            discovery["availability_topic"],
+            "rust_led_strip/availability"
+        );
+        assert!(
+            discovery["effect_list"]
+                .as_array()
+                .unwrap()
+                .contains(&Value::from(EFFECT)),
+            "{}",
+            discovery
+        );
+    }
+
+    #[test]
+    fn state_follows_program_of_segments() {
+        let (mut bridge, client, animation) = start_bridge();
+
+        bridge.publish_state().unwrap();
+        assert_eq!(
+            client.take_published("rust_led_strip/state"),
+            [serde_json::json!({"state": "ON", "brightness": 255})]
+        );
+
+        // Unchanged state is not published again
+        bridge.publish_state().unwrap();
+        assert!(client.take_published("rust_led_strip/state").is_empty());
+
+        // Program is played over http
+        set_program(
+            &mut animation.state.lock().unwrap(),
+            Some("rainbow".to_string()),
+        );
+        bridge.refresh().unwrap();
+        assert_eq!(
+            client.take_published("rust_led_strip/state"),
+            [serde_json::json!({"state": "ON", "brightness": 255, "effect": "rainbow"})]
+        );
+
+        // Segments run different programs
+        animation
+            .state
+            .lock()
+            .unwrap()
+            .segments
+            .push(segment(10, Some("fire")));
+        bridge
+            .on_message("rust_led_strip/set", br#"{"state": "OFF"}"#)
+            .unwrap();
+        assert_eq!(
+            client.take_published("rust_led_strip/state"),
+            [serde_json::json!({"state": "OFF", "brightness": 255})]
+        );
+    }
+
+    #[test]
+    fn missing_password_keeps_stored_one() {
+        let stored = MqttConfig {
+            password: Some("secret".to_string()),
+            ..Default::default()
+        };
+
+        let mut config = MqttConfig::default();
+        config.keep_password(Some(stored.clone()));
+        assert_eq!(config.password.as_deref(), Some("secret"));
+
+        let mut config = MqttConfig {
+            password: Some("new".to_string()),
+            ..Default::default()
+        };
+        config.keep_password(Some(stored.clone()));
+        assert_eq!(config.password.as_deref(), Some("new"));
+
+        let mut config = MqttConfig {
+            password: Some(String::new()),
+            ..Default::default()
+        };
+        config.keep_password(Some(stored));
+        assert_eq!(config.password, None);
+    }
+}
@@ -0,0 +1,135 @@
+#[cfg(feature = "esp")]
+pub use esp::{NvsNamespace, StorageError};
+
+#[cfg(feature = "host")]
+pub use memory::{NvsNamespace, StorageError};
+
+#[cfg(feature = "esp")]
+mod esp {
+    use embedded_svc::storage::{RawStorage, StorageBase};
+    use esp_idf_svc::nvs::{EspCustomNvsPartition, EspDefaultNvsPartition, EspNvs};
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+
+    pub use esp_idf_sys::EspError as StorageError;
+
+    // Default nvs partition can be taken only once, so it is shared between all namespaces
+    static NVS_DEFAULT_PARTITION: Mutex<Option<EspDefaultNvsPartition>> = Mutex::new(None);
+    // Same for custom partitions, keyed by their label
+    static NVS_CUSTOM_PARTITIONS: Mutex<BTreeMap<String, EspCustomNvsPartition>> =
+        Mutex::new(BTreeMap::new());
+
+    fn default_nvs_partition() -> Result<EspDefaultNvsPartition, StorageError> {
+        let mut partition = NVS_DEFAULT_PARTITION.lock().unwrap();
+
+        if partition.is_none() {
+            *partition = Some(EspDefaultNvsPartition::take()?);
+        }
+
+        Ok(partition.as_ref().unwrap().clone())
+    }
+
+    fn custom_nvs_partition(label: &str) -> Result<EspCustomNvsPartition, StorageError> {
+        let mut partitions = NVS_CUSTOM_PARTITIONS.lock().unwrap();
+
+        if !partitions.contains_key(label) {
+            partitions.insert(label.to_string(), EspCustomNvsPartition::take(label)?);
+        }
+
+        Ok(partitions[label].clone())
+    }
+
+    pub struct NvsNamespace(Box<dyn RawStorage<Error = StorageError> + Send>);
+
+    impl NvsNamespace {
+        /// Namespace in default `nvs` partition
+        pub fn open(namespace: &str) -> Result<Self, StorageError> {
+            Ok(Self(Box::new(EspNvs::new(
+                default_nvs_partition()?,
+                namespace,
+                true,
+            )?)))
+        }
+
+        /// Namespace in nvs partition with given label from `partitions.csv`
+        pub fn open_in(partition: &str, namespace: &str) -> Result<Self, StorageError> {
+            Ok(Self(Box::new(EspNvs::new(
+                custom_nvs_partition(partition)?,
+                namespace,
+                true,
+            )?)))
+        }
+
+        pub fn get_blob(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError> {
+            let len = match self.0.len(key)? {
+                Some(len) => len,
+                None => return Ok(None),
+            };
+
+            let mut buf = vec![0; len];
+            let blob_len = self.0.get_raw(key, &mut buf)?.map(|blob| blob.len());
+
+            Ok(blob_len.map(|blob_len| {
+                buf.truncate(blob_len);
+                buf
+            }))
+        }
+
+        pub fn set_blob(&mut self, key: &str, blob: &[u8]) -> Result<(), StorageError> {
+            self.0.set_raw(key, blob)?;
+
+            Ok(())
+        }
+
+        pub fn remove(&mut self, key: &str) -> Result<bool, StorageError> {
+            StorageBase::remove(&mut *self.0, key)
+        }
+    }
+}
+
+#[cfg(feature = "host")]
+mod memory {
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+
+    /// In-memory storage never fails
+    pub type StorageError = std::convert::Infallible;
+
+    // Blobs of all namespaces, keyed by namespace and key, lost on exit
+    static MEMORY_NVS: Mutex<BTreeMap<(String, String), Vec<u8>>> = Mutex::new(BTreeMap::new());
+
+    /// Emulated nvs namespace for host build
+    pub struct NvsNamespace(String);
+
+    impl NvsNamespace {
+        pub fn open(namespace: &str) -> Result<Self, StorageError> {
+            Ok(Self(namespace.to_string()))
+        }
+
+        /// Namespaces of other partitions are kept apart from default one by label prefix
+        pub fn open_in(partition: &str, namespace: &str) -> Result<Self, StorageError> {
+            Ok(Self(format!("{}/{}", partition, namespace)))
+        }
+
+        fn key(&self, key: &str) -> (String, String) {
+            (self.0.clone(), key.to_string())
+        }
+
+        pub fn get_blob(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError> {
+            Ok(MEMORY_NVS.lock().unwrap().get(&self.key(key)).cloned())
+        }
+
+        pub fn set_blob(&mut self, key: &str, blob: &[u8]) -> Result<(), StorageError> {
+            MEMORY_NVS
+                .lock()
+                .unwrap()
+                .insert(self.key(key), blob.to_vec());
+
+            Ok(())
+        }
+
+        pub fn remove(&mut self, key: &str) -> Result<bool, StorageError> {
+            Ok(MEMORY_NVS.lock().unwrap().remove(&self.key(key)).is_some())
+        }
+    }
+}
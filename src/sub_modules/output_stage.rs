@@ -0,0 +1,173 @@
+use super::led_strip_animations::AnimationConfig;
+use serde::{Deserialize, Serialize};
+use smart_leds_trait::White;
+use ws2812_esp32_rmt_driver::{RGB8, RGBW8};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WhiteMode {
+    /// White channel of every pixel has same brightness
+    #[default]
+    Fixed,
+    /// White channel is derived from color of every pixel
+    Extract,
+}
+
+/// Converts colors produced by vm into colors written to led strip,
+/// applies gamma, color correction and master brightness
+pub struct OutputStage {
+    // Lookup tables for red, green, blue and white channels
+    luts: [[u8; 256]; 4],
+    white_channel: bool,
+    white_mode: WhiteMode,
+    white_brightness: u8,
+    // Color of white led, brightest channel is 255
+    white_led: RGB8,
+}
+
+// Approximation of black body color by Tanner Helland
+fn color_temperature_to_rgb(kelvin: u16) -> RGB8 {
+    let temp = kelvin as f32 / 100.0;
+
+    let r = if temp <= 66.0 {
+        255.0
+    } else {
+        329.69873 * (temp - 60.0).powf(-0.13320476)
+    };
+    let g = if temp <= 66.0 {
+        99.4708 * temp.ln() - 161.11957
+    } else {
+        288.12216 * (temp - 60.0).powf(-0.07551485)
+    };
+    let b = if temp >= 66.0 {
+        255.0
+    } else if temp <= 19.0 {
+        0.0
+    } else {
+        138.51773 * (temp - 10.0).ln() - 305.0448
+    };
+
+    RGB8::new(
+        r.clamp(0.0, 255.0) as u8,
+        g.clamp(0.0, 255.0) as u8,
+        b.clamp(0.0, 255.0) as u8,
+    )
+}
+
+/// Moves common part of color, which white led can produce, into white channel
+fn extract_white(pixel: RGB8, white_led: RGB8) -> (RGB8, u8) {
+    let white = [
+        (pixel.r, white_led.r),
+        (pixel.g, white_led.g),
+        (pixel.b, white_led.b),
+    ]
+    .iter()
+    .filter(|(_, led)| *led != 0)
+    .map(|&(c, led)| c as u32 * u8::MAX as u32 / led as u32)
+    .min()
+    .unwrap_or(0)
+    .min(u8::MAX as u32);
+
+    let remove = |c: u8, led: u8| c.saturating_sub((white * led as u32 / u8::MAX as u32) as u8);
+
+    (
+        RGB8::new(
+            remove(pixel.r, white_led.r),
+            remove(pixel.g, white_led.g),
+            remove(pixel.b, white_led.b),
+        ),
+        white as u8,
+    )
+}
+
+fn build_lut(gamma: f32, brightness: u8, correction: u8) -> [u8; 256] {
+    let scale = brightness as f32 * correction as f32 / (255.0 * 255.0);
+
+    let mut lut = [0; 256];
+    for (i, val) in lut.iter_mut().enumerate() {
+        *val = ((i as f32 / 255.0).powf(gamma) * scale * 255.0).round() as u8;
+    }
+
+    lut
+}
+
+impl OutputStage {
+    pub fn new(config: &AnimationConfig) -> Self {
+        let [r, g, b] = config.color_correction;
+
+        Self {
+            luts: [r, g, b, u8::MAX]
+                .map(|correction| build_lut(config.gamma, config.brightness, correction)),
+            white_channel: config.strip_type.white_channel,
+            white_mode: config.white_mode,
+            white_brightness: config.white_brightness,
+            white_led: color_temperature_to_rgb(config.white_temperature_k),
+        }
+    }
+
+    pub fn process(&self, pixel: RGB8) -> RGBW8 {
+        // Strips without white channel should not account it in power estimation
+        let (pixel, white) = match (self.white_channel, self.white_mode) {
+            (false, _) => (pixel, 0),
+            (true, WhiteMode::Fixed) => (pixel, self.white_brightness),
+            (true, WhiteMode::Extract) => extract_white(pixel, self.white_led),
+        };
+
+        RGBW8::new_alpha(
+            self.luts[0][pixel.r as usize],
+            self.luts[1][pixel.g as usize],
+            self.luts[2][pixel.b as usize],
+            White(self.luts[3][white as usize]),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct PowerStatus {
+    /// Estimated current of last frame before limiting
+    pub estimated_ma: u32,
+    /// Whether last frame was scaled down to fit into power budget
+    pub throttled: bool,
+    /// How many frames were scaled down since boot
+    pub throttled_frames: u32,
+}
+
+/// Estimates current drawn by frame and scales it down when it exceeds `power_limit_ma`,
+/// `ma_per_channel` - current of single fully lit channel, zero `power_limit_ma` disables limiting
+pub fn limit_power(
+    frame: &mut [RGBW8],
+    ma_per_channel: u8,
+    power_limit_ma: u32,
+    status: &mut PowerStatus,
+) {
+    // Products of channels with current or with large limits do not fit into u32
+    let channels_sum: u64 = frame
+        .iter()
+        .map(|p| p.r as u64 + p.g as u64 + p.b as u64 + p.a.0 as u64)
+        .sum();
+    let estimated_ma = channels_sum * ma_per_channel as u64 / u8::MAX as u64;
+
+    status.estimated_ma = u32::try_from(estimated_ma).unwrap_or(u32::MAX);
+    status.throttled = power_limit_ma != 0 && estimated_ma > power_limit_ma as u64;
+
+    if status.throttled {
+        status.throttled_frames = status.throttled_frames.wrapping_add(1);
+
+        let scale = |c: u8| (c as u64 * power_limit_ma as u64 / estimated_ma) as u8;
+        for p in frame.iter_mut() {
+            *p = RGBW8::new_alpha(scale(p.r), scale(p.g), scale(p.b), White(scale(p.a.0)));
+        }
+    }
+}
+
+/// Scales whole frame by `level`, used while led strip fades on or off
+pub fn dim(frame: &mut [RGBW8], level: u8) {
+    if level == u8::MAX {
+        return;
+    }
+
+    let scale = |c: u8| (c as u32 * level as u32 / u8::MAX as u32) as u8;
+    for p in frame.iter_mut() {
+        *p = RGBW8::new_alpha(scale(p.r), scale(p.g), scale(p.b), White(scale(p.a.0)));
+    }
+}
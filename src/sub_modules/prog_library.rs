@@ -0,0 +1,124 @@
+use super::nvs_storage::{NvsNamespace, StorageError};
+use anyhow::Result;
+use log::info;
+use prog_container::{Container, ContainerError, Metadata};
+use thiserror::Error;
+
+// Library has own nvs partition after application, so settings in `nvs` partition are not
+// crowded out and partitions of older firmware keep their place
+const PROG_LIBRARY_PARTITION: &str = "prog_lib";
+const PROG_LIBRARY_NAMESPACE: &str = "prog_library";
+const PROG_INDEX_NAMESPACE: &str = "prog_index";
+const PROG_INDEX_KEY: &str = "names";
+// Program name is used as nvs key, which is limited to 15 characters
+pub const MAX_PROG_NAME: usize = 15;
+
+#[derive(Error, Debug)]
+pub enum ProgLibraryError {
+    #[error("Program name should be 1-15 characters long and contain only [a-zA-Z0-9_-]")]
+    InvalidName,
+    #[error("Program not found")]
+    NotFound,
+}
+
+fn open_nvs(namespace: &str) -> std::result::Result<NvsNamespace, StorageError> {
+    NvsNamespace::open_in(PROG_LIBRARY_PARTITION, namespace)
+}
+
+fn validate_name(name: &str) -> std::result::Result<(), ProgLibraryError> {
+    let valid_chars = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    if name.is_empty() || name.len() > MAX_PROG_NAME || !valid_chars {
+        return Err(ProgLibraryError::InvalidName);
+    }
+
+    Ok(())
+}
+
+// Nvs does not allow to iterate keys, so names of stored programs are kept in separate index
+fn load_index() -> Result<Vec<String>> {
+    let nvs = open_nvs(PROG_INDEX_NAMESPACE)?;
+
+    Ok(match nvs.get_blob(PROG_INDEX_KEY)? {
+        Some(blob) => serde_json::from_slice(&blob)?,
+        None => Vec::new(),
+    })
+}
+
+fn store_index(names: &[String]) -> Result<()> {
+    open_nvs(PROG_INDEX_NAMESPACE)?.set_blob(PROG_INDEX_KEY, &serde_json::to_vec(names)?)?;
+
+    Ok(())
+}
+
+/// Names of all programs stored in library
+pub fn list() -> Result<Vec<String>> {
+    load_index()
+}
+
+/// Program container stored under given name
+pub fn load(name: &str) -> Result<Vec<u8>> {
+    validate_name(name)?;
+
+    let nvs = open_nvs(PROG_LIBRARY_NAMESPACE)?;
+
+    Ok(nvs.get_blob(name)?.ok_or(ProgLibraryError::NotFound)?)
+}
+
+/// Stored program unwrapped from its container, programs stored by incompatible firmware are rejected
+pub fn load_container(name: &str) -> Result<Container> {
+    Ok(Container::decode(&load(name)?)?)
+}
+
+/// Wraps bare bytecode, stored by firmware before program containers, into containers
+pub fn migrate_legacy() -> Result<()> {
+    for name in list()? {
+        let blob = load(&name)?;
+        if Container::decode(&blob) != Err(ContainerError::BadMagic) {
+            continue;
+        }
+
+        info!("Wrapping legacy program {} into container", name);
+        let container = Container::new(
+            Metadata {
+                name: name.clone(),
+                ..Default::default()
+            },
+            blob,
+        )?;
+        open_nvs(PROG_LIBRARY_NAMESPACE)?.set_blob(&name, &container.encode())?;
+    }
+
+    Ok(())
+}
+
+/// Store program container, program with same name is overwritten
+pub fn store(name: &str, container: &[u8]) -> Result<()> {
+    validate_name(name)?;
+
+    open_nvs(PROG_LIBRARY_NAMESPACE)?.set_blob(name, container)?;
+
+    let mut names = load_index()?;
+    if !names.iter().any(|n| n == name) {
+        names.push(name.to_string());
+        store_index(&names)?;
+    }
+
+    Ok(())
+}
+
+pub fn remove(name: &str) -> Result<()> {
+    validate_name(name)?;
+
+    if !open_nvs(PROG_LIBRARY_NAMESPACE)?.remove(name)? {
+        return Err(ProgLibraryError::NotFound.into());
+    }
+
+    let mut names = load_index()?;
+    names.retain(|n| n != name);
+    store_index(&names)?;
+
+    Ok(())
+}
@@ -0,0 +1,371 @@
+use super::led_strip_animations::Messages;
+use super::nvs_storage::NvsNamespace;
+use anyhow::Result;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use std::net::UdpSocket;
+use std::sync::mpsc::SyncSender;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use thiserror::Error;
+use ws2812_esp32_rmt_driver::RGB8;
+
+const REALTIME_NAMESPACE: &str = "realtime";
+const REALTIME_KEY: &str = "config";
+
+pub const DDP_PORT: u16 = 4048;
+pub const E131_PORT: u16 = 5568;
+pub const ART_NET_PORT: u16 = 6454;
+
+// Largest packet of supported protocols is DDP one, 10 byte header + 1440 bytes of data
+const MAX_PACKET_SIZE: usize = 1500;
+// Whole rgb pixels fitting into 512 DMX channels
+const CHANNELS_PER_UNIVERSE: usize = 510;
+const MAX_E131_UNIVERSE: u16 = 63999;
+const MAX_ART_NET_UNIVERSE: u16 = 0x7fff;
+
+/// Every protocol is off by default, as any host in network could take over led strip
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct RealtimeConfig {
+    pub ddp: bool,
+    pub e131: bool,
+    pub art_net: bool,
+    /// Universe of first led, every next 170 leds use next universe
+    pub start_universe: u16,
+    /// Programs are shown again when no frames arrive for this time
+    pub timeout_ms: u32,
+}
+
+impl Default for RealtimeConfig {
+    fn default() -> Self {
+        Self {
+            ddp: false,
+            e131: false,
+            art_net: false,
+            start_universe: 1,
+            timeout_ms: 2500,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum RealtimeError {
+    #[error("timeout_ms should be in range [100, 60000]")]
+    InvalidTimeout,
+    #[error("start_universe should be in range [1, {MAX_E131_UNIVERSE}] for e131")]
+    InvalidE131Universe,
+    #[error("start_universe should be in range [0, {MAX_ART_NET_UNIVERSE}] for art_net")]
+    InvalidArtNetUniverse,
+}
+
+impl RealtimeConfig {
+    pub fn validate(&self) -> std::result::Result<(), RealtimeError> {
+        if !(100..=60000).contains(&self.timeout_ms) {
+            return Err(RealtimeError::InvalidTimeout);
+        }
+        if self.e131 && !(1..=MAX_E131_UNIVERSE).contains(&self.start_universe) {
+            return Err(RealtimeError::InvalidE131Universe);
+        }
+        if self.art_net && self.start_universe > MAX_ART_NET_UNIVERSE {
+            return Err(RealtimeError::InvalidArtNetUniverse);
+        }
+
+        Ok(())
+    }
+
+    /// Stored config overrides defaults on boot
+    pub fn load() -> Result<Option<Self>> {
+        let nvs = NvsNamespace::open(REALTIME_NAMESPACE)?;
+
+        Ok(match nvs.get_blob(REALTIME_KEY)? {
+            Some(blob) => Some(serde_json::from_slice(&blob)?),
+            None => None,
+        })
+    }
+
+    pub fn store(&self) -> Result<()> {
+        NvsNamespace::open(REALTIME_NAMESPACE)?
+            .set_blob(REALTIME_KEY, &serde_json::to_vec(self)?)?;
+
+        Ok(())
+    }
+
+    pub fn erase() -> Result<()> {
+        NvsNamespace::open(REALTIME_NAMESPACE)?.remove(REALTIME_KEY)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Protocol {
+    Ddp,
+    E131,
+    ArtNet,
+}
+
+/// Pixel data of single packet
+struct Packet<'a> {
+    /// Offset of first byte of data in frame
+    offset: usize,
+    data: &'a [u8],
+    /// Universe of DMX based protocols, relative to start universe
+    universe: Option<usize>,
+    /// Frame is complete and should be shown
+    push: bool,
+}
+
+fn parse_ddp(packet: &[u8]) -> Option<Packet> {
+    const VERSION_MASK: u8 = 0xc0;
+    const VERSION_1: u8 = 0x40;
+    const TIMECODE: u8 = 0x10;
+    const PUSH: u8 = 0x01;
+    // Zero is reserved, but some senders use it for default output
+    const DEFAULT_OUTPUT_IDS: [u8; 2] = [0, 1];
+
+    let flags = *packet.first()?;
+    if flags & VERSION_MASK != VERSION_1 || !DEFAULT_OUTPUT_IDS.contains(packet.get(3)?) {
+        return None;
+    }
+    let header_len = if flags & TIMECODE != 0 { 14 } else { 10 };
+    let offset = u32::from_be_bytes(packet.get(4..8)?.try_into().ok()?) as usize;
+    let len = u16::from_be_bytes(packet.get(8..10)?.try_into().ok()?) as usize;
+    let data = packet.get(header_len..)?;
+
+    Some(Packet {
+        offset,
+        data: &data[..len.min(data.len())],
+        universe: None,
+        push: flags & PUSH != 0,
+    })
+}
+
+fn parse_e131(packet: &[u8], start_universe: u16) -> Option<Packet> {
+    const ACN_PACKET_IDENTIFIER: &[u8] = b"ASC-E1.17\0\0\0";
+    const VECTOR_ROOT_E131_DATA: u32 = 0x04;
+    const VECTOR_E131_DATA_PACKET: u32 = 0x02;
+    const PREVIEW_DATA: u8 = 0x80;
+    const DATA_START: usize = 126;
+
+    let be_u32 = |range: std::ops::Range<usize>| {
+        packet
+            .get(range)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u32::from_be_bytes)
+    };
+    if packet.get(4..16)? != ACN_PACKET_IDENTIFIER
+        || be_u32(18..22)? != VECTOR_ROOT_E131_DATA
+        || be_u32(40..44)? != VECTOR_E131_DATA_PACKET
+        || packet.get(112)? & PREVIEW_DATA != 0
+        // Only null start code carries dimmer levels
+        || *packet.get(125)? != 0
+    {
+        return None;
+    }
+    let universe = u16::from_be_bytes(packet.get(113..115)?.try_into().ok()?);
+    // Property value count includes start code
+    let len = u16::from_be_bytes(packet.get(123..125)?.try_into().ok()?).checked_sub(1)? as usize;
+    let data = packet.get(DATA_START..)?;
+
+    dmx_packet(universe, start_universe, &data[..len.min(data.len())])
+}
+
+fn parse_art_net(packet: &[u8], start_universe: u16) -> Option<Packet> {
+    const ART_NET_ID: &[u8] = b"Art-Net\0";
+    const OP_DMX: u16 = 0x5000;
+    const DATA_START: usize = 18;
+
+    if packet.get(..8)? != ART_NET_ID
+        || u16::from_le_bytes(packet.get(8..10)?.try_into().ok()?) != OP_DMX
+    {
+        return None;
+    }
+    let universe = u16::from_le_bytes(packet.get(14..16)?.try_into().ok()?) & MAX_ART_NET_UNIVERSE;
+    let len = u16::from_be_bytes(packet.get(16..18)?.try_into().ok()?) as usize;
+    let data = packet.get(DATA_START..)?;
+
+    dmx_packet(universe, start_universe, &data[..len.min(data.len())])
+}
+
+// Every universe holds whole pixels, so pixels are not split between universes
+fn dmx_packet(universe: u16, start_universe: u16, data: &[u8]) -> Option<Packet> {
+    let universe = universe.checked_sub(start_universe)? as usize;
+
+    Some(Packet {
+        offset: universe * CHANNELS_PER_UNIVERSE,
+        data: &data[..data.len().min(CHANNELS_PER_UNIVERSE)],
+        universe: Some(universe),
+        push: false,
+    })
+}
+
+/// Assembles frame from packets of one protocol
+struct FrameBuffer {
+    protocol: Protocol,
+    socket: UdpSocket,
+    // `[r, g, b]` bytes of every led
+    data: Vec<u8>,
+    // Universes received since last shown frame, for DMX based protocols
+    received_universes: Vec<bool>,
+}
+
+impl FrameBuffer {
+    fn new(protocol: Protocol, socket: UdpSocket, led_quantity: usize) -> Self {
+        let universes = (led_quantity * 3).div_ceil(CHANNELS_PER_UNIVERSE).max(1);
+
+        Self {
+            protocol,
+            socket,
+            data: vec![0; led_quantity * 3],
+            received_universes: vec![false; universes],
+        }
+    }
+
+    fn pixels(&self) -> Vec<RGB8> {
+        self.data
+            .chunks_exact(3)
+            .map(|c| RGB8::new(c[0], c[1], c[2]))
+            .collect()
+    }
+
+    /// Copies packet into frame, returns assembled frame when it should be shown.
+    /// DMX based protocols have no push flag, so frame is shown when every universe of strip is received,
+    /// or when universe is received again, in case sender does not cover whole strip
+    fn add_packet(&mut self, packet: Packet) -> Option<Vec<RGB8>> {
+        let mut frame = None;
+
+        if let Some(universe) = packet.universe {
+            match self.received_universes.get_mut(universe) {
+                Some(true) => {
+                    frame = Some(self.pixels());
+                    self.received_universes.fill(false);
+                    self.received_universes[universe] = true;
+                }
+                Some(received) => *received = true,
+                // Universe is beyond strip
+                None => return None,
+            }
+        }
+
+        if let Some(frame_data) = self.data.get_mut(packet.offset..) {
+            let len = packet.data.len().min(frame_data.len());
+            frame_data[..len].copy_from_slice(&packet.data[..len]);
+        }
+
+        if packet.push || self.received_universes.iter().all(|&received| received) {
+            self.received_universes.fill(false);
+            frame = Some(self.pixels());
+        }
+
+        frame
+    }
+}
+
+/// Receives pixels streamed over udp and sends them to led strip, overriding programs
+pub struct RealtimeReceiver {
+    config: RealtimeConfig,
+    buffers: Vec<FrameBuffer>,
+    tx: SyncSender<Messages>,
+}
+
+impl RealtimeReceiver {
+    /// Listens on ports of enabled protocols, protocols which failed to bind are skipped.
+    /// Frames are assembled for up to `led_quantity` leds
+    pub fn new(config: RealtimeConfig, led_quantity: usize, tx: SyncSender<Messages>) -> Self {
+        let buffers = [
+            (Protocol::Ddp, config.ddp, DDP_PORT),
+            (Protocol::E131, config.e131, E131_PORT),
+            (Protocol::ArtNet, config.art_net, ART_NET_PORT),
+        ]
+        .into_iter()
+        .filter(|&(_, enabled, _)| enabled)
+        .filter_map(|(protocol, _, port)| {
+            let socket = UdpSocket::bind(("0.0.0.0", port))
+                .and_then(|socket| socket.set_nonblocking(true).map(|_| socket));
+            match socket {
+                Ok(socket) => {
+                    info!("Listening for {:?} on udp port {}", protocol, port);
+                    Some(FrameBuffer::new(protocol, socket, led_quantity))
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to listen for {:?} on udp port {}: {:?}",
+                        protocol, port, e
+                    );
+                    None
+                }
+            }
+        })
+        .collect();
+
+        Self {
+            config,
+            buffers,
+            tx,
+        }
+    }
+
+    fn parse<'a>(&self, protocol: Protocol, packet: &'a [u8]) -> Option<Packet<'a>> {
+        match protocol {
+            Protocol::Ddp => parse_ddp(packet),
+            Protocol::E131 => parse_e131(packet, self.config.start_universe),
+            Protocol::ArtNet => parse_art_net(packet, self.config.start_universe),
+        }
+    }
+
+    // Reads all pending packets, returns whether any packet was received
+    fn poll(&mut self, packet_buf: &mut [u8]) -> Result<bool> {
+        let timeout = Duration::from_millis(self.config.timeout_ms.into());
+        let mut received = false;
+
+        for i in 0..self.buffers.len() {
+            loop {
+                let len = match self.buffers[i].socket.recv(packet_buf) {
+                    Ok(len) => len,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        error!("Failed to receive realtime packet: {:?}", e);
+                        break;
+                    }
+                };
+                received = true;
+
+                let Some(packet) = self.parse(self.buffers[i].protocol, &packet_buf[..len]) else {
+                    continue;
+                };
+                if let Some(frame) = self.buffers[i].add_packet(packet) {
+                    self.tx.send(Messages::RealtimeFrame(frame, timeout))?;
+                }
+            }
+        }
+
+        Ok(received)
+    }
+
+    pub fn daemon(self, stack_size: usize) -> Result<JoinHandle<()>> {
+        Ok(std::thread::Builder::new()
+            .stack_size(stack_size)
+            .spawn(move || {
+                let mut receiver = self;
+                if receiver.buffers.is_empty() {
+                    info!("Realtime streaming is disabled");
+                    return;
+                }
+
+                let mut packet_buf = vec![0; MAX_PACKET_SIZE];
+                loop {
+                    match receiver.poll(&mut packet_buf) {
+                        Ok(true) => {}
+                        Ok(false) => std::thread::sleep(Duration::from_millis(2)),
+                        Err(e) => {
+                            error!("Realtime receiver stopped: {:?}", e);
+                            return;
+                        }
+                    }
+                }
+            })?)
+    }
+}
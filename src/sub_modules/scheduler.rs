@@ -0,0 +1,362 @@
+use super::led_strip_animations::{
+    ConfigError, Messages, PowerParams, ProgParams, ReceivedAnimationConfig,
+};
+use super::nvs_storage::NvsNamespace;
+use super::prog_library;
+use animation_lang::program::Program;
+use anyhow::Result;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use thiserror::Error;
+
+#[cfg(feature = "esp")]
+use esp_idf_sys as libc_time;
+#[cfg(feature = "host")]
+use libc as libc_time;
+
+const SCHEDULE_NAMESPACE: &str = "schedule";
+const SCHEDULE_KEY: &str = "schedule";
+const ALL_WEEKDAYS: u8 = 0b0111_1111;
+// Anything before this year means that time is not synced by sntp yet
+const MIN_SYNCED_YEAR: i32 = 2023;
+// Wall clock minute is repeated at most hour later, when daylight saving time ends
+const MAX_CLOCK_SHIFT_MINUTES: i64 = 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+#[serde(deny_unknown_fields)]
+pub enum ScheduleAction {
+    Play {
+        program: String,
+        #[serde(default)]
+        config: Option<ReceivedAnimationConfig>,
+    },
+    Config {
+        config: ReceivedAnimationConfig,
+    },
+    On {
+        #[serde(default)]
+        fade_ms: u16,
+    },
+    Off {
+        #[serde(default)]
+        fade_ms: u16,
+    },
+}
+
+fn all_weekdays() -> u8 {
+    ALL_WEEKDAYS
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleRule {
+    pub hour: u8,
+    pub minute: u8,
+    /// Bit mask of days when rule is active, bit 0 - Sunday, bit 6 - Saturday
+    #[serde(default = "all_weekdays")]
+    pub weekdays: u8,
+    pub action: ScheduleAction,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct Schedule {
+    /// POSIX TZ string, e.g. "CET-1CEST,M3.5.0,M10.5.0/3"
+    pub timezone: String,
+    pub rules: Vec<ScheduleRule>,
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self {
+            timezone: "UTC0".to_string(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ScheduleError {
+    #[error("Rule {0}: hour should be in range [0, 23]")]
+    InvalidHour(usize),
+    #[error("Rule {0}: minute should be in range [0, 59]")]
+    InvalidMinute(usize),
+    #[error("Rule {0}: weekdays mask should be in range [0, 127]")]
+    InvalidWeekdays(usize),
+    #[error("Rule {0}: {1}")]
+    InvalidConfig(usize, ConfigError),
+    #[error("Timezone should not be empty")]
+    EmptyTimezone,
+}
+
+impl Schedule {
+    /// Configs of rules are checked against `max_led_quantity` leds of outputs
+    pub fn validate(&self, max_led_quantity: usize) -> std::result::Result<(), ScheduleError> {
+        if self.timezone.is_empty() {
+            return Err(ScheduleError::EmptyTimezone);
+        }
+
+        for (i, rule) in self.rules.iter().enumerate() {
+            if rule.hour > 23 {
+                return Err(ScheduleError::InvalidHour(i));
+            }
+            if rule.minute > 59 {
+                return Err(ScheduleError::InvalidMinute(i));
+            }
+            if rule.weekdays > ALL_WEEKDAYS {
+                return Err(ScheduleError::InvalidWeekdays(i));
+            }
+            match &rule.action {
+                ScheduleAction::Play {
+                    config: Some(config),
+                    ..
+                }
+                | ScheduleAction::Config { config } => config
+                    .validate(max_led_quantity)
+                    .map_err(|e| ScheduleError::InvalidConfig(i, e))?,
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn load() -> Result<Option<Self>> {
+        let nvs = NvsNamespace::open(SCHEDULE_NAMESPACE)?;
+
+        Ok(match nvs.get_blob(SCHEDULE_KEY)? {
+            Some(blob) => Some(serde_json::from_slice(&blob)?),
+            None => None,
+        })
+    }
+
+    pub fn store(&self) -> Result<()> {
+        NvsNamespace::open(SCHEDULE_NAMESPACE)?
+            .set_blob(SCHEDULE_KEY, &serde_json::to_vec(self)?)?;
+
+        Ok(())
+    }
+
+    fn apply_timezone(&self) {
+        std::env::set_var("TZ", &self.timezone);
+        unsafe { libc_time::tzset() };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LocalTime {
+    /// Minutes since unix epoch, not affected by daylight saving time
+    epoch_minute: i64,
+    year: i32,
+    yday: i32,
+    wday: i32,
+    hour: i32,
+    minute: i32,
+}
+
+impl LocalTime {
+    fn now() -> Self {
+        let (now, tm) = unsafe {
+            let mut tm: libc_time::tm = std::mem::zeroed();
+            let now = libc_time::time(std::ptr::null_mut());
+            libc_time::localtime_r(&now, &mut tm);
+            (now, tm)
+        };
+
+        Self {
+            epoch_minute: now as i64 / 60,
+            year: tm.tm_year + 1900,
+            yday: tm.tm_yday,
+            wday: tm.tm_wday,
+            hour: tm.tm_hour,
+            minute: tm.tm_min,
+        }
+    }
+}
+
+impl ScheduleRule {
+    fn matches(&self, time: &LocalTime) -> bool {
+        self.hour as i32 == time.hour
+            && self.minute as i32 == time.minute
+            && self.weekdays & (1 << time.wday) != 0
+    }
+}
+
+pub enum SchedulerCmd {
+    Get,
+    Set(Schedule),
+}
+
+pub struct GetScheduleAPI(SyncSender<SchedulerCmd>, Receiver<Schedule>);
+
+impl GetScheduleAPI {
+    pub fn get(&self) -> Result<Schedule> {
+        self.0.send(SchedulerCmd::Get)?;
+        Ok(self.1.recv()?)
+    }
+}
+
+pub struct SetScheduleAPI(SyncSender<SchedulerCmd>, Receiver<Result<()>>);
+
+impl SetScheduleAPI {
+    pub fn set(&self, schedule: Schedule) -> Result<Result<()>> {
+        self.0.send(SchedulerCmd::Set(schedule))?;
+        Ok(self.1.recv()?)
+    }
+}
+
+pub struct SchedulerCommunication {
+    pub get_api: GetScheduleAPI,
+    pub set_api: SetScheduleAPI,
+}
+
+pub struct Scheduler {
+    schedule: Schedule,
+    tx: SyncSender<Messages>,
+    // Epoch minute of last fire of every rule, so wall clock minute repeated by end of
+    // daylight saving time does not fire rule again
+    last_fired: Vec<Option<i64>>,
+}
+
+impl Scheduler {
+    pub fn new(tx: SyncSender<Messages>) -> Self {
+        let schedule = match Schedule::load() {
+            Ok(Some(schedule)) => schedule,
+            Ok(None) => Default::default(),
+            Err(e) => {
+                error!("Failed to load schedule: {:?}", e);
+                Default::default()
+            }
+        };
+        schedule.apply_timezone();
+
+        Self {
+            last_fired: vec![None; schedule.rules.len()],
+            schedule,
+            tx,
+        }
+    }
+
+    fn send_config(&self, config: ReceivedAnimationConfig) -> Result<()> {
+        // Nobody waits for applied config, so reply is dropped
+        let (applied_config_tx, _) = sync_channel(1);
+        self.tx
+            .send(Messages::NewConfig(config, applied_config_tx))?;
+
+        Ok(())
+    }
+
+    fn send_power(&self, on: bool, fade_ms: u16) -> Result<()> {
+        self.tx
+            .send(Messages::SetPower(PowerParams { on, fade_ms }))?;
+
+        Ok(())
+    }
+
+    fn run_action(&self, action: &ScheduleAction) -> Result<()> {
+        info!("Running scheduled action: {:?}", action);
+
+        match action {
+            ScheduleAction::Play { program, config } => {
+                let container = prog_library::load_container(program)?;
+                if let Some(config) = config {
+                    self.send_config(config.clone())?;
+                }
+                // Fps of rule config takes precedence over default one of program
+                let default_fps = container
+                    .metadata()
+                    .default_fps
+                    .filter(|_| config.as_ref().and_then(|config| config.fps).is_none());
+                let (res_tx, res_rx) = sync_channel(1);
+                self.tx.send(Messages::NewProg(
+                    Program::from_binary(container.into_code()),
+                    ProgParams {
+                        name: Some(program.clone()),
+                        default_fps,
+                        ..Default::default()
+                    },
+                    res_tx,
+                ))?;
+                res_rx.recv()??;
+                // Played program should be visible, even if led strip was turned off
+                self.send_power(true, 0)?;
+            }
+            ScheduleAction::Config { config } => self.send_config(config.clone())?,
+            ScheduleAction::On { fade_ms } => self.send_power(true, *fade_ms)?,
+            ScheduleAction::Off { fade_ms } => self.send_power(false, *fade_ms)?,
+        }
+
+        Ok(())
+    }
+
+    fn check_rules(&mut self, time: &LocalTime) {
+        if time.year < MIN_SYNCED_YEAR {
+            return;
+        }
+
+        for (i, rule) in self.schedule.rules.iter().enumerate() {
+            let fired_recently = self.last_fired[i]
+                .is_some_and(|minute| time.epoch_minute - minute <= MAX_CLOCK_SHIFT_MINUTES);
+            if !rule.matches(time) || fired_recently {
+                continue;
+            }
+
+            self.last_fired[i] = Some(time.epoch_minute);
+            if let Err(e) = self.run_action(&rule.action) {
+                error!("Scheduled action failed: {:?}", e);
+            }
+        }
+    }
+
+    pub fn daemon(self, stack_size: usize) -> Result<(JoinHandle<()>, SchedulerCommunication)> {
+        let (cmd_tx, cmd_rx) = sync_channel(1);
+        let (get_tx, get_rx) = sync_channel(0);
+        let (set_res_tx, set_res_rx) = sync_channel(0);
+
+        let thread_handle =
+            std::thread::Builder::new()
+                .stack_size(stack_size)
+                .spawn(move || {
+                    let mut scheduler = self;
+                    let mut last_checked_minute = None;
+                    loop {
+                        match cmd_rx.recv_timeout(Duration::from_secs(1)) {
+                            Ok(SchedulerCmd::Get) => {
+                                get_tx.send(scheduler.schedule.clone()).unwrap()
+                            }
+                            Ok(SchedulerCmd::Set(schedule)) => {
+                                let res = schedule.store();
+                                if res.is_ok() {
+                                    schedule.apply_timezone();
+                                    scheduler.last_fired = vec![None; schedule.rules.len()];
+                                    scheduler.schedule = schedule;
+                                }
+                                set_res_tx.send(res).unwrap();
+                            }
+                            Err(RecvTimeoutError::Timeout) => {}
+                            Err(RecvTimeoutError::Disconnected) => panic!(),
+                        }
+
+                        // Every minute is checked once, so rule fires once
+                        let now = LocalTime::now();
+                        if last_checked_minute != Some(now.epoch_minute) {
+                            scheduler.check_rules(&now);
+                            last_checked_minute = Some(now.epoch_minute);
+                        }
+                    }
+                })?;
+
+        let communication = SchedulerCommunication {
+            get_api: GetScheduleAPI(cmd_tx.clone(), get_rx),
+            set_api: SetScheduleAPI(cmd_tx, set_res_rx),
+        };
+
+        Ok((thread_handle, communication))
+    }
+}
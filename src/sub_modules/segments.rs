@@ -0,0 +1,384 @@
+use super::esp_random::EspRand;
+use super::nvs_storage::NvsNamespace;
+use super::transitions::{Transition, TransitionKind};
+use animation_lang::program::Program;
+use animation_lang::vm::{VMState, VMStateConfig, VM};
+use anyhow::Result;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use thiserror::Error;
+use ws2812_esp32_rmt_driver::RGB8;
+
+const SEGMENTS_NAMESPACE: &str = "segments";
+const SEGMENTS_LAYOUT_KEY: &str = "layout";
+pub const MAX_SEGMENTS: usize = 16;
+const INSTRUCTION_LIMIT: usize = 1_000_000;
+// Validation runs on http server task and blocks other requests, so first frame has smaller budget
+const VALIDATION_INSTRUCTION_LIMIT: usize = 50_000;
+
+/// Range of leds on strip, driven by its own program
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SegmentLayout {
+    pub start: usize,
+    pub length: usize,
+    #[serde(default)]
+    pub reversed: bool,
+}
+
+#[derive(Error, Debug)]
+pub enum SegmentError {
+    #[error("Too many segments, max {MAX_SEGMENTS}")]
+    TooMany,
+    #[error("Segment {0}: length should not be zero")]
+    ZeroLength(usize),
+    #[error("Segment {0}: should end within led strip of {1} leds")]
+    OutOfStrip(usize, usize),
+    #[error("Segment {0} does not exist")]
+    NotFound(usize),
+    #[error("Programs are not running, while led strip is off or shows realtime stream")]
+    NotRunning,
+}
+
+/// Every segment should lie within first `led_quantity` leds of strip
+pub fn validate_layouts(
+    layouts: &[SegmentLayout],
+    led_quantity: usize,
+) -> std::result::Result<(), SegmentError> {
+    if layouts.len() > MAX_SEGMENTS {
+        return Err(SegmentError::TooMany);
+    }
+    for (i, layout) in layouts.iter().enumerate() {
+        if layout.length == 0 {
+            return Err(SegmentError::ZeroLength(i));
+        }
+        if !matches!(layout.start.checked_add(layout.length), Some(end) if end <= led_quantity) {
+            return Err(SegmentError::OutOfStrip(i, led_quantity));
+        }
+    }
+
+    Ok(())
+}
+
+/// Custom layouts, `None` means single segment over whole strip
+pub fn load_layouts() -> Result<Option<Vec<SegmentLayout>>> {
+    let nvs = NvsNamespace::open(SEGMENTS_NAMESPACE)?;
+
+    Ok(match nvs.get_blob(SEGMENTS_LAYOUT_KEY)? {
+        Some(blob) => Some(serde_json::from_slice(&blob)?),
+        None => None,
+    })
+}
+
+pub fn store_layouts(layouts: &[SegmentLayout]) -> Result<()> {
+    NvsNamespace::open(SEGMENTS_NAMESPACE)?
+        .set_blob(SEGMENTS_LAYOUT_KEY, &serde_json::to_vec(layouts)?)?;
+
+    Ok(())
+}
+
+pub fn erase_layouts() -> Result<()> {
+    NvsNamespace::open(SEGMENTS_NAMESPACE)?.remove(SEGMENTS_LAYOUT_KEY)?;
+
+    Ok(())
+}
+
+/// Program loaded into segment
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProgramInfo {
+    /// FNV-1a hash of compiled program, as hex string
+    pub hash: String,
+    /// Name in program library, `None` for unnamed uploads
+    pub name: Option<String>,
+}
+
+impl ProgramInfo {
+    pub fn new(prog: &Program, name: Option<String>) -> Self {
+        let hash = prog.code().iter().fold(0x811c9dc5u32, |hash, &byte| {
+            (hash ^ byte as u32).wrapping_mul(0x01000193)
+        });
+
+        Self {
+            hash: format!("{:08x}", hash),
+            name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VmState {
+    Running,
+    Paused,
+    /// Program ended or failed, waiting for new one
+    Stopped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SegmentState {
+    pub layout: SegmentLayout,
+    pub vm: VmState,
+    pub program: ProgramInfo,
+    /// Error which stopped program, cleared by new program
+    pub last_error: Option<String>,
+}
+
+/// Runtime error, which stopped program
+#[derive(Debug, Clone, Serialize)]
+pub struct VmFault {
+    pub program: ProgramInfo,
+    /// Address of failed instruction
+    pub pc: usize,
+    pub error: String,
+}
+
+#[derive(Error, Debug)]
+pub enum ProgError {
+    #[error("Program is empty")]
+    Empty,
+    #[error("Program fails on first frame at instruction {pc}: {error}")]
+    Fault { pc: usize, error: String },
+}
+
+fn start_vm(led_quantity: usize, prog: Program, instruction_limit: usize) -> VMState {
+    VM::new(led_quantity, Default::default()).start(
+        prog,
+        VMStateConfig {
+            local_instruction_limit: Some(instruction_limit),
+            rng: Box::new(EspRand {}),
+            ..Default::default()
+        },
+    )
+}
+
+/// Runs first frame of program, so broken programs are rejected before they replace running one.
+/// First frame should fit into `VALIDATION_INSTRUCTION_LIMIT`, to keep validation short
+pub fn validate_prog(code: &[u8], led_quantity: usize) -> std::result::Result<(), ProgError> {
+    if code.is_empty() {
+        return Err(ProgError::Empty);
+    }
+
+    let mut vm_state = start_vm(
+        led_quantity,
+        Program::from_binary(code.to_vec()),
+        VALIDATION_INSTRUCTION_LIMIT,
+    );
+    let res = vm_state.next().map(|res| res.map(|_| ()));
+    match res {
+        Some(Err(e)) => Err(ProgError::Fault {
+            pc: vm_state.pc(),
+            error: format!("{:?}", e),
+        }),
+        _ => Ok(()),
+    }
+}
+
+enum VmStatus {
+    Running(VMState),
+    /// Program keeps its state, frames are produced only by single steps
+    Paused(VMState),
+    Stopped((VM, VMStateConfig)),
+}
+
+/// Control of running program, used to debug programs frame by frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VmCommand {
+    Pause,
+    Resume,
+    /// Pauses program and advances it by single frame
+    Step,
+}
+
+pub struct Segment {
+    layout: SegmentLayout,
+    // Always `Some`, option only allows to move status out while switching it
+    vm_status: Option<VmStatus>,
+    // Last frame produced by vm
+    vm_frame: Vec<RGB8>,
+    // Last frame shown on led strip, before output stage
+    shown_frame: Vec<RGB8>,
+    transition: Option<Transition>,
+    // Paused vm advances by one frame on next tick
+    step_requested: bool,
+    program: ProgramInfo,
+    last_error: Option<String>,
+    // Fault which was not yet collected by `take_fault`
+    fault: Option<VmFault>,
+}
+
+impl Segment {
+    pub fn new(layout: SegmentLayout, prog: Program) -> Self {
+        let program = ProgramInfo::new(&prog, None);
+        let vm_state = start_vm(layout.length, prog, INSTRUCTION_LIMIT);
+
+        Self {
+            layout,
+            vm_status: Some(VmStatus::Running(vm_state)),
+            vm_frame: Vec::with_capacity(layout.length),
+            shown_frame: Vec::with_capacity(layout.length),
+            transition: None,
+            step_requested: false,
+            program,
+            last_error: None,
+            fault: None,
+        }
+    }
+
+    pub fn layout(&self) -> SegmentLayout {
+        self.layout
+    }
+
+    pub fn shown_frame(&self) -> &[RGB8] {
+        &self.shown_frame
+    }
+
+    pub fn state(&self) -> SegmentState {
+        SegmentState {
+            layout: self.layout,
+            vm: match self.vm_status.as_ref().unwrap() {
+                VmStatus::Running(_) => VmState::Running,
+                VmStatus::Paused(_) => VmState::Paused,
+                VmStatus::Stopped(_) => VmState::Stopped,
+            },
+            program: self.program.clone(),
+            last_error: self.last_error.clone(),
+        }
+    }
+
+    /// Fault of program since last call, each fault is returned once
+    pub fn take_fault(&mut self) -> Option<VmFault> {
+        self.fault.take()
+    }
+
+    /// Starts new program, blending into it from currently shown frame.
+    /// Program of paused segment starts paused
+    pub fn set_prog(
+        &mut self,
+        prog: Program,
+        name: Option<String>,
+        transition: TransitionKind,
+        duration: Duration,
+    ) {
+        self.program = ProgramInfo::new(&prog, name);
+        self.last_error = None;
+        self.transition = Transition::new(transition, duration, self.shown_frame.clone());
+        self.vm_status = Some(match self.vm_status.take().unwrap() {
+            VmStatus::Running(vm_state) => {
+                let (vm, cfg, _) = vm_state.stop();
+                VmStatus::Running(vm.start(prog, cfg))
+            }
+            VmStatus::Paused(vm_state) => {
+                let (vm, cfg, _) = vm_state.stop();
+                VmStatus::Paused(vm.start(prog, cfg))
+            }
+            VmStatus::Stopped((vm, cfg)) => VmStatus::Running(vm.start(prog, cfg)),
+        });
+    }
+
+    /// Stopped vm has no program to control, so commands are ignored
+    pub fn control(&mut self, command: VmCommand) {
+        self.vm_status = Some(match (self.vm_status.take().unwrap(), command) {
+            (VmStatus::Running(vm_state), VmCommand::Pause) => VmStatus::Paused(vm_state),
+            (VmStatus::Paused(vm_state), VmCommand::Resume) => VmStatus::Running(vm_state),
+            (VmStatus::Running(vm_state) | VmStatus::Paused(vm_state), VmCommand::Step) => {
+                self.step_requested = true;
+                VmStatus::Paused(vm_state)
+            }
+            (status, _) => status,
+        });
+    }
+
+    /// Changes length of segment, running program is restarted if length changed
+    pub fn resize(&mut self, length: usize) {
+        if self.layout.length == length {
+            return;
+        }
+        self.layout.length = length;
+
+        self.vm_status = Some(match self.vm_status.take().unwrap() {
+            VmStatus::Running(vm_state) => {
+                info!("Restarting vm");
+                let (mut vm, cfg, prog) = vm_state.stop();
+                vm.set_stip_length(length);
+                VmStatus::Running(vm.start(prog, cfg))
+            }
+            VmStatus::Paused(vm_state) => {
+                info!("Restarting paused vm");
+                let (mut vm, cfg, prog) = vm_state.stop();
+                vm.set_stip_length(length);
+                VmStatus::Paused(vm.start(prog, cfg))
+            }
+            VmStatus::Stopped((mut vm, cfg)) => {
+                vm.set_stip_length(length);
+                VmStatus::Stopped((vm, cfg))
+            }
+        });
+    }
+
+    // Runs vm till next frame, vm which ended or failed is stopped
+    fn advance(&mut self, mut vm_state: VMState, frame_updated: &mut bool) -> VmStatus {
+        match vm_state.next() {
+            None => {
+                info!("Program ended");
+                info!("Halting VM and Waiting for new prog...");
+                let (vm, cfg, _) = vm_state.stop();
+                VmStatus::Stopped((vm, cfg))
+            }
+            Some(Err(e)) => {
+                let error = format!("{:?}", e);
+                let fault = VmFault {
+                    program: self.program.clone(),
+                    pc: vm_state.pc(),
+                    error,
+                };
+                error!(
+                    "Program failed at instruction {}: {}",
+                    fault.pc, fault.error
+                );
+                self.last_error = Some(fault.error.clone());
+                self.fault = Some(fault);
+                info!("Halting VM and Waiting for new prog...");
+                let (vm, cfg, _) = vm_state.stop();
+                VmStatus::Stopped((vm, cfg))
+            }
+            Some(Ok(v)) => {
+                self.vm_frame.clear();
+                self.vm_frame.extend(v.map(|c| RGB8::new(c.r, c.g, c.b)));
+                *frame_updated = true;
+                VmStatus::Running(vm_state)
+            }
+        }
+    }
+
+    /// Advances program by one frame, returns whether shown frame changed
+    pub fn next_frame(&mut self) -> bool {
+        let mut frame_updated = false;
+
+        self.vm_status = Some(match self.vm_status.take().unwrap() {
+            VmStatus::Running(vm_state) => self.advance(vm_state, &mut frame_updated),
+            VmStatus::Paused(vm_state) if std::mem::take(&mut self.step_requested) => {
+                match self.advance(vm_state, &mut frame_updated) {
+                    VmStatus::Running(vm_state) => VmStatus::Paused(vm_state),
+                    stopped => stopped,
+                }
+            }
+            status => status,
+        });
+
+        // Transition keeps going, even if vm does not produce new frames
+        if let Some(transition) = &self.transition {
+            transition.blend(&self.vm_frame, &mut self.shown_frame);
+            if transition.is_finished() {
+                self.transition = None;
+            }
+            true
+        } else if frame_updated {
+            self.shown_frame.clear();
+            self.shown_frame.extend_from_slice(&self.vm_frame);
+            true
+        } else {
+            false
+        }
+    }
+}
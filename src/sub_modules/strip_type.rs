@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+use ws2812_esp32_rmt_driver::RGBW8;
+
+/// Order in which color channels are transmitted to led
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorOrder {
+    Rgb,
+    Rbg,
+    #[default]
+    Grb,
+    Gbr,
+    Brg,
+    Bgr,
+}
+
+/// Layout of data sent to led strip. Every strip is driven with 800 kHz ws2812 bit timing,
+/// which sk6812, ws2812b and 800 kHz ws2811 accept
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StripType {
+    pub color_order: ColorOrder,
+    /// White channel is transmitted after color channels
+    pub white_channel: bool,
+}
+
+impl Default for StripType {
+    fn default() -> Self {
+        // sk6812 GRBW
+        Self {
+            color_order: ColorOrder::Grb,
+            white_channel: true,
+        }
+    }
+}
+
+impl StripType {
+    /// Appends pixel to data transmitted to led strip
+    pub fn encode(&self, pixel: RGBW8, data: &mut Vec<u8>) {
+        let (r, g, b) = (pixel.r, pixel.g, pixel.b);
+
+        data.extend_from_slice(&match self.color_order {
+            ColorOrder::Rgb => [r, g, b],
+            ColorOrder::Rbg => [r, b, g],
+            ColorOrder::Grb => [g, r, b],
+            ColorOrder::Gbr => [g, b, r],
+            ColorOrder::Brg => [b, r, g],
+            ColorOrder::Bgr => [b, g, r],
+        });
+        if self.white_channel {
+            data.push(pixel.a.0);
+        }
+    }
+}
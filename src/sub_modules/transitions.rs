@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use ws2812_esp32_rmt_driver::RGB8;
+
+const BLACK: RGB8 = RGB8 { r: 0, g: 0, b: 0 };
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransitionKind {
+    /// New program is shown immediately
+    #[default]
+    Cut,
+    Crossfade,
+    /// New program gradually replaces old one from start to end of strip
+    Wipe,
+    FadeThroughBlack,
+}
+
+/// Blends last shown frame of outgoing program into frames of incoming program
+pub struct Transition {
+    kind: TransitionKind,
+    from: Vec<RGB8>,
+    started: Instant,
+    duration: Duration,
+}
+
+fn mix(from: u8, to: u8, progress: u32) -> u8 {
+    ((from as u32 * (u8::MAX as u32 - progress) + to as u32 * progress) / u8::MAX as u32) as u8
+}
+
+// Progress of timed change in range [0, 255]
+fn timed_progress(started: Instant, duration: Duration) -> u32 {
+    let elapsed = started.elapsed().min(duration);
+
+    (elapsed.as_millis() * u8::MAX as u128 / duration.as_millis().max(1)) as u32
+}
+
+fn mix_rgb(from: RGB8, to: RGB8, progress: u32) -> RGB8 {
+    RGB8::new(
+        mix(from.r, to.r, progress),
+        mix(from.g, to.g, progress),
+        mix(from.b, to.b, progress),
+    )
+}
+
+impl Transition {
+    /// Returns `None` when transition would not be visible
+    pub fn new(kind: TransitionKind, duration: Duration, from: Vec<RGB8>) -> Option<Self> {
+        if kind == TransitionKind::Cut || duration.is_zero() {
+            return None;
+        }
+
+        Some(Self {
+            kind,
+            from,
+            started: Instant::now(),
+            duration,
+        })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.started.elapsed() >= self.duration
+    }
+
+    pub fn blend(&self, to: &[RGB8], out: &mut Vec<RGB8>) {
+        let progress = timed_progress(self.started, self.duration);
+        let from = |i: usize| self.from.get(i).copied().unwrap_or(BLACK);
+
+        out.clear();
+        out.extend(to.iter().enumerate().map(|(i, &to)| match self.kind {
+            TransitionKind::Cut => to,
+            TransitionKind::Crossfade => mix_rgb(from(i), to, progress),
+            TransitionKind::Wipe => {
+                if (i as u32 * u8::MAX as u32) < progress * to.len() as u32 {
+                    to
+                } else {
+                    from(i)
+                }
+            }
+            TransitionKind::FadeThroughBlack => {
+                if progress < 128 {
+                    mix_rgb(from(i), BLACK, progress * 2)
+                } else {
+                    mix_rgb(BLACK, to, (progress - 128) * 2 + 1)
+                }
+            }
+        }));
+    }
+}
+
+/// Gradual change of output level, used when led strip is turned on or off
+pub struct Fade {
+    from: u8,
+    to: u8,
+    started: Instant,
+    duration: Duration,
+}
+
+impl Fade {
+    /// Returns `None` when fade would not be visible
+    pub fn new(from: u8, to: u8, duration: Duration) -> Option<Self> {
+        if from == to || duration.is_zero() {
+            return None;
+        }
+
+        Some(Self {
+            from,
+            to,
+            started: Instant::now(),
+            duration,
+        })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.started.elapsed() >= self.duration
+    }
+
+    pub fn level(&self) -> u8 {
+        mix(
+            self.from,
+            self.to,
+            timed_progress(self.started, self.duration),
+        )
+    }
+}
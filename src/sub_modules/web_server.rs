@@ -1,14 +1,19 @@
-use crate::sub_modules::led_strip_animations::{AnimationConfig, Messages};
+use crate::sub_modules::http_router::{ApiError, ApiRequest, ApiResponse, Router};
+use crate::sub_modules::led_outputs::OutputsConfig;
+use crate::sub_modules::led_strip_animations::{saved_prog, Messages, PowerParams, ProgParams};
+use crate::sub_modules::mqtt::MqttConfig;
+use crate::sub_modules::prog_library::{self, ProgLibraryError};
+use crate::sub_modules::realtime::RealtimeConfig;
+use crate::sub_modules::scheduler::{Schedule, SchedulerCommunication};
+use crate::sub_modules::segments::{self, SegmentError, SegmentLayout, VmCommand};
 use crate::sub_modules::wifi_manager::wifi_creds::WifiCredentials;
 use crate::sub_modules::wifi_manager::{TryConnectArgs, WifiManagerCommunication};
-use crate::T_CONFIG;
 use animation_lang::program::Program;
 use embedded_svc::http::Method;
-use embedded_svc::io::adapters::ToStd;
-use embedded_svc::io::Write;
-use esp_idf_svc::http::server::EspHttpServer;
-use std::io::Read;
-use std::sync::mpsc::{Receiver, SyncSender};
+use percent_encoding::percent_decode_str;
+use prog_container::{Container, ContainerError};
+use serde::Deserialize;
+use std::sync::mpsc::{self, SyncSender};
 
 use super::led_strip_animations::ReceivedAnimationConfig;
 
@@ -16,154 +21,540 @@ static WASM_BLOB: &[u8] = include_bytes!(env!("WASM_BLOB_PATH"));
 static JS_BLOB: &[u8] = include_bytes!(env!("JS_BLOB_PATH"));
 static HTML_BLOB: &[u8] = include_bytes!("../../frontend/index.html");
 
+/// Percent decoded name of program in `/programs/{name}`, longer paths do not name program
+fn prog_name_from_path(path: &str) -> Result<String, ApiError> {
+    let name = path
+        .strip_prefix("/programs/")
+        .filter(|name| !name.contains('/'))
+        .ok_or_else(|| ApiError::not_found(format!("No route for {}", path)))?;
+
+    percent_decode_str(name)
+        .decode_utf8()
+        .map(|name| name.into_owned())
+        .map_err(|_| ApiError::invalid_value(ProgLibraryError::InvalidName))
+}
+
+/// Segment targeted by request, `None` means every segment
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+struct SegmentParams {
+    segment: Option<usize>,
+}
+
+// Errors of program library caused by request are reported to client
+fn prog_library_error(e: anyhow::Error) -> anyhow::Error {
+    if e.is::<ContainerError>() {
+        return ApiError::invalid_program(e).into();
+    }
+    match e.downcast_ref::<ProgLibraryError>() {
+        Some(ProgLibraryError::InvalidName) => ApiError::invalid_value(e).into(),
+        Some(ProgLibraryError::NotFound) => ApiError::not_found(e).into(),
+        None => e,
+    }
+}
+
+// Upload is buffered and decoded into few copies before it is stored, container with
+// bytecode of typical program is well below 1 KiB, so limit keeps copies small for ram of esp
+const MAX_PROG_UPLOAD: usize = 4 * 1024;
+// Base64 encodes every 3 bytes as 4 symbols
+const MAX_PROG_UPLOAD_BASE64: usize = MAX_PROG_UPLOAD.div_ceil(3) * 4;
+
+// Uploaded program should be compatible container with bytecode, which does not fail on first frame
+fn decode_prog(data: &[u8], led_quantity: usize) -> Result<Container, ApiError> {
+    let container = Container::decode(data).map_err(ApiError::invalid_program)?;
+    segments::validate_prog(container.code(), led_quantity).map_err(ApiError::invalid_program)?;
+
+    Ok(container)
+}
+
+// Runs uploaded program container, with params from query of request
+fn send_prog(
+    tx: &SyncSender<Messages>,
+    req: &ApiRequest,
+    data: &[u8],
+    led_quantity: usize,
+) -> anyhow::Result<ApiResponse> {
+    let container = decode_prog(data, led_quantity)?;
+    let params = ProgParams {
+        name: Some(container.metadata().name.clone()).filter(|name| !name.is_empty()),
+        default_fps: container.metadata().default_fps,
+        ..req.query_params()?
+    };
+
+    let (res_tx, res_rx) = mpsc::sync_channel(1);
+    tx.send(Messages::NewProg(
+        Program::from_binary(container.into_code()),
+        params,
+        res_tx,
+    ))?;
+    res_rx.recv()?.map_err(ApiError::not_found)?;
+
+    Ok(ApiResponse::ok())
+}
+
+#[cfg(feature = "esp")]
+mod frame_stream {
+    use crate::sub_modules::led_strip_animations::{FrameListener, Messages};
+    use anyhow::anyhow;
+    use embedded_svc::ws::FrameType;
+    use esp_idf_svc::http::server::ws::{EspHttpWsConnection, EspHttpWsDetachedSender};
+    use log::info;
+    use std::sync::mpsc::{self, SyncSender, TrySendError};
+
+    // Frames waiting to be sent to single session, newer frames are dropped while queue is full
+    const FRAME_QUEUE_LEN: usize = 2;
+    const SENDER_STACK_SIZE: usize = 4 * 1024;
+
+    // Websocket session, which receives frames from animation thread. Frames are sent by
+    // own thread of session, so slow client or busy httpd task never blocks animation thread
+    struct WsFrameListener(SyncSender<Vec<u8>>);
+
+    impl WsFrameListener {
+        fn spawn(mut sender: EspHttpWsDetachedSender) -> anyhow::Result<Self> {
+            let (frame_tx, frame_rx) = mpsc::sync_channel::<Vec<u8>>(FRAME_QUEUE_LEN);
+
+            std::thread::Builder::new()
+                .stack_size(SENDER_STACK_SIZE)
+                .spawn(move || {
+                    // Dropped receiver tells animation thread, that session is gone
+                    for frame in frame_rx {
+                        if let Err(e) = sender.send(FrameType::Binary(false), &frame) {
+                            info!("Frame stream session is gone: {:?}", e);
+                            break;
+                        }
+                    }
+                })?;
+
+            Ok(Self(frame_tx))
+        }
+    }
+
+    impl FrameListener for WsFrameListener {
+        fn send_frame(&mut self, frame: &[u8]) -> anyhow::Result<()> {
+            match self.0.try_send(frame.to_vec()) {
+                Ok(()) | Err(TrySendError::Full(_)) => Ok(()),
+                Err(TrySendError::Disconnected(_)) => Err(anyhow!("Websocket session is closed")),
+            }
+        }
+    }
+
+    pub fn handle_ws(
+        ws: &mut EspHttpWsConnection,
+        tx: &SyncSender<Messages>,
+    ) -> anyhow::Result<()> {
+        if ws.is_new() {
+            info!("Frame stream session {} opened", ws.session());
+            let listener = WsFrameListener::spawn(ws.create_detached_sender()?)?;
+            tx.send(Messages::AddFrameListener(Box::new(listener)))?;
+        } else if ws.is_closed() {
+            info!("Frame stream session {} closed", ws.session());
+        } else {
+            // Stream is one way, messages from client are discarded
+            let mut buf = [0; 64];
+            ws.recv(&mut buf)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "esp")]
 pub fn web_server(
     tx: SyncSender<Messages>,
-    applied_config_rx: Receiver<AnimationConfig>,
     wifi_manager_communication: WifiManagerCommunication,
-) -> anyhow::Result<EspHttpServer> {
-    let mut server = EspHttpServer::new(&Default::default())?;
+    scheduler_communication: SchedulerCommunication,
+    outputs_config: OutputsConfig,
+) -> anyhow::Result<esp_idf_svc::http::server::EspHttpServer> {
+    use esp_idf_svc::http::server::{Configuration, EspHttpServer};
 
-    // Frontend
-    server.fn_handler("/", Method::Get, |req| {
-        req.into_ok_response()?.write_all(HTML_BLOB)?;
+    let router = api_router(
+        tx.clone(),
+        wifi_manager_communication,
+        scheduler_communication,
+        outputs_config,
+    );
+    // Default of 32 handlers is too few for all routes, websocket takes one more
+    let max_uri_handlers = router.route_count() + 1;
 
-        Ok(())
+    let mut server = EspHttpServer::new(&Configuration {
+        uri_match_wildcard: true,
+        max_uri_handlers,
+        ..Default::default()
     })?;
 
-    server.fn_handler("/get_wasm_blob", Method::Get, |req| {
-        req.into_response(200, None, &[("Content-Type", "application/wasm")])?
-            .write_all(WASM_BLOB)?;
+    // Live frames of led strip, as `[r, g, b, w]` bytes of every led
+    server.ws_handler("/ws/frames", move |ws| frame_stream::handle_ws(ws, &tx))?;
 
-        Ok(())
-    })?;
+    router.register(&mut server, max_uri_handlers - 1)?;
 
-    server.fn_handler("/get_js_blob", Method::Get, |req| {
-        req.into_response(200, None, &[("Content-Type", "text/javascript")])?
-            .write_all(JS_BLOB)?;
+    Ok(server)
+}
 
-        Ok(())
-    })?;
+/// Routes of web server, shared between firmware and host build
+pub fn api_router(
+    tx: SyncSender<Messages>,
+    wifi_manager_communication: WifiManagerCommunication,
+    scheduler_communication: SchedulerCommunication,
+    outputs_config: OutputsConfig,
+) -> Router {
+    let mut router = Router::new();
 
-    server.fn_handler("/get_max_led_quantity", Method::Get, |req| {
-        req.into_response(200, None, &[("Content-Type", "text/plain")])?
-            .write_all(T_CONFIG.led_quantity.to_string().as_bytes())?;
+    // Frontend
+    router.route("/", Method::Get, |_| {
+        Ok(ApiResponse::new(200)
+            .with_content_type("text/html")
+            .with_body(HTML_BLOB))
+    });
 
-        Ok(())
-    })?;
+    router.route("/get_wasm_blob", Method::Get, |_| {
+        Ok(ApiResponse::new(200)
+            .with_content_type("application/wasm")
+            .with_body(WASM_BLOB))
+    });
+
+    router.route("/get_js_blob", Method::Get, |_| {
+        Ok(ApiResponse::new(200)
+            .with_content_type("text/javascript")
+            .with_body(JS_BLOB))
+    });
+
+    let max_led_quantity = outputs_config.total_led_quantity();
+    router.route("/get_max_led_quantity", Method::Get, move |_| {
+        ApiResponse::json(&max_led_quantity)
+    });
 
     // Led related
-    server.fn_handler("/set_conf", Method::Post, {
+    router.route("/set_conf", Method::Post, {
         let tx = tx.clone();
-        move |mut req| {
-            let new_config: ReceivedAnimationConfig =
-                match serde_json::from_reader(ToStd::new(&mut req)) {
-                    Ok(cfg) => cfg,
-                    Err(e) => {
-                        let message = e.to_string();
-                        req.into_response(400, Some(&message), &[])?
-                            .write_all(message.as_bytes())?;
-                        return Ok(());
-                    }
-                };
+        move |req| {
+            let new_config: ReceivedAnimationConfig = req.json()?;
+
+            new_config
+                .validate(max_led_quantity)
+                .map_err(ApiError::invalid_value)?;
+
+            let (applied_config_tx, applied_config_rx) = mpsc::sync_channel(1);
+            tx.send(Messages::NewConfig(new_config, applied_config_tx))?;
 
-            tx.send(Messages::NewConfig(new_config))?;
-            req.into_ok_response()?
-                .write_all(format!("Applied config: {:?}", applied_config_rx.recv()?).as_bytes())?;
-            Ok(())
+            ApiResponse::json(&applied_config_rx.recv()?)
         }
-    })?;
+    });
+
+    router.route("/reset_conf", Method::Post, {
+        let tx = tx.clone();
+        move |_| {
+            let (applied_config_tx, applied_config_rx) = mpsc::sync_channel(1);
+            tx.send(Messages::ResetConfig(applied_config_tx))?;
 
-    server.fn_handler("/send_prog_base64", Method::Post, {
-        move |mut req| {
-            let mut body = Vec::new();
-            ToStd::new(&mut req).read_to_end(&mut body)?;
-            let bin_prog = match base64::decode(body) {
-                Ok(bin_prog) => bin_prog,
-                Err(e) => {
-                    let message = e.to_string();
-                    req.into_response(400, Some(&message), &[])?
-                        .write_all(message.as_bytes())?;
-                    return Ok(());
-                }
-            };
-
-            tx.send(Messages::NewProg(Program::from_binary(bin_prog)))?;
-
-            req.into_response(200, None, &[])?;
-            Ok(())
+            ApiResponse::json(&applied_config_rx.recv()?)
         }
-    })?;
+    });
 
-    // Wifi Related
-    server.fn_handler("/wifi/store_credentials", Method::Post, move |req| {
-        match wifi_manager_communication.store_credentials_api.store()? {
-            Ok(_) => {}
-            Err(e) => {
-                let message = e.to_string();
-                req.into_response(400, Some(&message), &[])?
-                    .write_all(message.as_bytes())?;
-                return Ok(());
-            }
-        };
+    router.route("/status", Method::Get, {
+        let tx = tx.clone();
+        move |_| {
+            let (status_tx, status_rx) = mpsc::sync_channel(1);
+            tx.send(Messages::GetStatus(status_tx))?;
 
-        Ok(())
-    })?;
+            ApiResponse::json(&status_rx.recv()?)
+        }
+    });
 
-    server.fn_handler("/wifi/erase_credentials", Method::Post, move |_| {
-        WifiCredentials::erase()?;
+    router.route("/state", Method::Get, {
+        let tx = tx.clone();
+        move |_| {
+            let (state_tx, state_rx) = mpsc::sync_channel(1);
+            tx.send(Messages::GetState(state_tx))?;
 
-        Ok(())
-    })?;
+            ApiResponse::json(&state_rx.recv()?)
+        }
+    });
 
-    server.fn_handler("/wifi/scan", Method::Get, move |req| {
-        let access_points = wifi_manager_communication.scan_api.scan()?;
-        let serialized_access_points = serde_json::to_vec(&access_points)?;
+    router.route("/faults", Method::Get, {
+        let tx = tx.clone();
+        move |_| {
+            let (faults_tx, faults_rx) = mpsc::sync_channel(1);
+            tx.send(Messages::GetFaults(faults_tx))?;
 
-        req.into_ok_response()?
-            .write_all(&serialized_access_points)?;
+            ApiResponse::json(&faults_rx.recv()?)
+        }
+    });
 
-        Ok(())
-    })?;
+    router.route("/faults", Method::Delete, {
+        let tx = tx.clone();
+        move |_| {
+            tx.send(Messages::ClearFaults)?;
 
-    server.fn_handler("/wifi/connect", Method::Post, move |mut req| {
-        let body_reader = ToStd::new(&mut req);
-        let connect_args: TryConnectArgs = match serde_json::from_reader(body_reader) {
-            Ok(cfg) => cfg,
-            Err(e) => {
-                let message = e.to_string();
-                req.into_response(400, Some(&message), &[])?
-                    .write_all(message.as_bytes())?;
-                return Ok(());
-            }
-        };
+            Ok(ApiResponse::ok())
+        }
+    });
 
-        wifi_manager_communication
-            .connect_api
-            .try_connect(connect_args)?;
+    router.route("/power", Method::Post, {
+        let tx = tx.clone();
+        move |req| {
+            let params: PowerParams = req.json()?;
 
-        Ok(())
-    })?;
+            tx.send(Messages::SetPower(params))?;
 
-    server.fn_handler("/wifi/disconnect", Method::Post, move |req| {
-        match wifi_manager_communication.disconnect_api.disconnect()? {
-            Ok(_) => {}
-            Err(e) => {
-                let message = e.to_string();
-                req.into_response(400, Some(&message), &[])?
-                    .write_all(message.as_bytes())?;
-                return Ok(());
-            }
+            Ok(ApiResponse::ok())
+        }
+    });
+
+    router.route_limited("/send_prog", Method::Post, MAX_PROG_UPLOAD, {
+        let tx = tx.clone();
+        move |req| send_prog(&tx, req, &req.body, max_led_quantity)
+    });
+
+    router.route_limited("/send_prog_base64", Method::Post, MAX_PROG_UPLOAD_BASE64, {
+        let tx = tx.clone();
+        move |req| {
+            let data = base64::decode(&req.body).map_err(ApiError::from)?;
+
+            send_prog(&tx, req, &data, max_led_quantity)
+        }
+    });
+
+    // Program control, to debug programs frame by frame
+    for (uri, command) in [
+        ("/vm/pause", VmCommand::Pause),
+        ("/vm/resume", VmCommand::Resume),
+        ("/vm/step", VmCommand::Step),
+    ] {
+        let tx = tx.clone();
+        router.route(uri, Method::Post, move |req| {
+            let params: SegmentParams = req.query_params()?;
+
+            let (res_tx, res_rx) = mpsc::sync_channel(1);
+            tx.send(Messages::ControlVm(command, params.segment, res_tx))?;
+            res_rx.recv()?.map_err(|e| match e {
+                SegmentError::NotFound(_) => ApiError::not_found(e),
+                e => ApiError::rejected(e),
+            })?;
+
+            Ok(ApiResponse::ok())
+        });
+    }
+
+    router.route("/erase_saved_prog", Method::Post, |_| {
+        saved_prog::erase()?;
+
+        Ok(ApiResponse::ok())
+    });
+
+    // Led outputs, applied after reboot, so only outputs driving leds since boot are reported
+    router.route("/outputs", Method::Get, move |_| {
+        ApiResponse::json(&outputs_config)
+    });
+
+    router.route("/outputs", Method::Put, |req| {
+        let new_outputs: OutputsConfig = req.json()?;
+
+        new_outputs.validate().map_err(ApiError::invalid_value)?;
+
+        new_outputs.store()?;
+
+        Ok(ApiResponse::ok())
+    });
+
+    router.route("/outputs", Method::Delete, |_| {
+        OutputsConfig::erase()?;
+
+        Ok(ApiResponse::ok())
+    });
+
+    // Realtime streaming, applied after reboot
+    router.route("/realtime", Method::Get, |_| {
+        ApiResponse::json(&RealtimeConfig::load()?.unwrap_or_default())
+    });
+
+    router.route("/realtime", Method::Put, |req| {
+        let new_config: RealtimeConfig = req.json()?;
+
+        new_config.validate().map_err(ApiError::invalid_value)?;
+
+        new_config.store()?;
+
+        Ok(ApiResponse::ok())
+    });
+
+    router.route("/realtime", Method::Delete, |_| {
+        RealtimeConfig::erase()?;
+
+        Ok(ApiResponse::ok())
+    });
+
+    // Mqtt broker, applied after reboot
+    router.route("/mqtt", Method::Get, |_| {
+        let config = MqttConfig::load()?.unwrap_or_default();
+
+        // Password is write only
+        ApiResponse::json(&MqttConfig {
+            password: None,
+            ..config
+        })
+    });
+
+    router.route("/mqtt", Method::Put, |req| {
+        let mut new_config: MqttConfig = req.json()?;
+
+        new_config.validate().map_err(ApiError::invalid_value)?;
+
+        new_config.keep_password(MqttConfig::load()?);
+
+        new_config.store()?;
+
+        Ok(ApiResponse::ok())
+    });
+
+    router.route("/mqtt", Method::Delete, |_| {
+        MqttConfig::erase()?;
+
+        Ok(ApiResponse::ok())
+    });
+
+    // Segments
+    router.route("/segments", Method::Get, {
+        let tx = tx.clone();
+        move |_| {
+            let (layouts_tx, layouts_rx) = mpsc::sync_channel(1);
+            tx.send(Messages::GetSegments(layouts_tx))?;
+
+            ApiResponse::json(&layouts_rx.recv()?)
+        }
+    });
+
+    router.route("/segments", Method::Put, {
+        let tx = tx.clone();
+        move |req| {
+            let layouts: Vec<SegmentLayout> = req.json()?;
+
+            let (res_tx, res_rx) = mpsc::sync_channel(1);
+            tx.send(Messages::SetSegments(layouts, res_tx))?;
+            res_rx.recv()?.map_err(ApiError::invalid_value)?;
+
+            Ok(ApiResponse::ok())
+        }
+    });
+
+    // Program library
+    router.route("/programs", Method::Get, |_| {
+        ApiResponse::json(&prog_library::list()?)
+    });
+
+    router.route("/programs/*", Method::Get, |req| {
+        let bin_prog =
+            prog_library::load(&prog_name_from_path(req.path())?).map_err(prog_library_error)?;
+
+        ApiResponse::json(&base64::encode(bin_prog))
+    });
+
+    router.route_limited(
+        "/programs/*",
+        Method::Put,
+        MAX_PROG_UPLOAD_BASE64,
+        move |req| {
+            let name = prog_name_from_path(req.path())?;
+            let data = base64::decode(&req.body).map_err(ApiError::from)?;
+            decode_prog(&data, max_led_quantity)?;
+
+            prog_library::store(&name, &data).map_err(prog_library_error)?;
+
+            Ok(ApiResponse::ok())
+        },
+    );
+
+    router.route("/programs/*", Method::Delete, |req| {
+        prog_library::remove(&prog_name_from_path(req.path())?).map_err(prog_library_error)?;
+
+        Ok(ApiResponse::ok())
+    });
+
+    router.route("/programs/*", Method::Post, move |req| {
+        let path = req
+            .path()
+            .strip_suffix("/play")
+            .ok_or_else(|| ApiError::not_found(format!("No route for {}", req.path())))?;
+
+        let name = prog_name_from_path(path)?;
+        let container = prog_library::load_container(&name).map_err(prog_library_error)?;
+        let params = ProgParams {
+            name: Some(name),
+            default_fps: container.metadata().default_fps,
+            ..req.query_params()?
         };
-        Ok(())
-    })?;
 
-    server.fn_handler("/wifi/status", Method::Get, move |req| {
-        let status = wifi_manager_communication.status_api.get_status()?;
+        let (res_tx, res_rx) = mpsc::sync_channel(1);
+        tx.send(Messages::NewProg(
+            Program::from_binary(container.into_code()),
+            params,
+            res_tx,
+        ))?;
+        res_rx.recv()?.map_err(ApiError::not_found)?;
 
-        serde_json::to_writer(ToStd::new(req.into_ok_response()?), &status)?;
+        Ok(ApiResponse::ok())
+    });
 
-        Ok(())
-    })?;
+    // Scheduler
+    let SchedulerCommunication { get_api, set_api } = scheduler_communication;
+    router.route("/schedule", Method::Get, move |_| {
+        ApiResponse::json(&get_api.get()?)
+    });
 
-    Ok(server)
+    router.route("/schedule", Method::Put, move |req| {
+        let schedule: Schedule = req.json()?;
+
+        schedule
+            .validate(max_led_quantity)
+            .map_err(ApiError::invalid_value)?;
+
+        set_api.set(schedule)??;
+
+        Ok(ApiResponse::ok())
+    });
+
+    // Wifi Related
+    let WifiManagerCommunication {
+        scan_api,
+        status_api,
+        connect_api,
+        disconnect_api,
+        store_credentials_api,
+    } = wifi_manager_communication;
+
+    router.route("/wifi/store_credentials", Method::Post, move |_| {
+        store_credentials_api.store()?.map_err(ApiError::rejected)?;
+
+        Ok(ApiResponse::ok())
+    });
+
+    router.route("/wifi/erase_credentials", Method::Post, |_| {
+        WifiCredentials::erase()?;
+
+        Ok(ApiResponse::ok())
+    });
+
+    router.route("/wifi/scan", Method::Get, move |_| {
+        ApiResponse::json(&scan_api.scan()?)
+    });
+
+    router.route("/wifi/connect", Method::Post, move |req| {
+        let connect_args: TryConnectArgs = req.json()?;
+
+        connect_api.try_connect(connect_args)?;
+
+        Ok(ApiResponse::ok())
+    });
+
+    router.route("/wifi/disconnect", Method::Post, move |_| {
+        disconnect_api.disconnect()?.map_err(ApiError::rejected)?;
+
+        Ok(ApiResponse::ok())
+    });
+
+    router.route("/wifi/status", Method::Get, move |_| {
+        ApiResponse::json(&status_api.get_status()?)
+    });
+
+    router
 }
@@ -1,16 +1,11 @@
-use crate::T_CONFIG;
 use anyhow::Result;
-use embedded_svc::wifi::{AccessPointConfiguration, AccessPointInfo, Configuration};
-use esp_idf_hal::peripheral;
-use esp_idf_svc::eventloop::EspSystemEventLoop;
-use log::info;
+use embedded_svc::wifi::AccessPointInfo;
 use serde::{Deserialize, Serialize};
 use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
 use std::thread::JoinHandle;
 use std::time::Duration;
 use thiserror::Error;
 use wifi_creds::WifiCredentials;
-use wifi_states::{Scan, WifiBase, WifiState};
 
 // pub mod net_utils {
 //     use anyhow::{bail, Result};
@@ -33,9 +28,7 @@ use wifi_states::{Scan, WifiBase, WifiState};
 // }
 
 pub mod wifi_creds {
-    use embedded_svc::storage::RawStorage;
-    use esp_idf_svc::nvs::{EspNvs, EspNvsPartition, NvsDefault};
-    use esp_idf_sys::EspError;
+    use crate::sub_modules::nvs_storage::{NvsNamespace, StorageError};
     use serde::Deserialize;
 
     pub const MAX_SSID: usize = 32;
@@ -44,7 +37,7 @@ pub mod wifi_creds {
     const WIFI_SSID_NAMESPACE: &str = "wifi_ssid";
     const WIFI_PASS_NAMESPACE: &str = "wifi_pass";
 
-    #[derive(Default, Debug, PartialEq, Deserialize)]
+    #[derive(Default, Debug, Clone, PartialEq, Deserialize)]
     #[serde(default)]
     #[serde(deny_unknown_fields)]
     pub struct WifiCredentials {
@@ -57,16 +50,13 @@ pub mod wifi_creds {
         pub fn load() -> anyhow::Result<Option<WifiCredentials>> {
             let nvs = Self::open_nvs()?;
 
-            let mut ssid_buf = [0; MAX_SSID];
-            let mut pass_buf = [0; MAX_PASS];
-
             if let (Some(ssid), Some(pass)) = (
-                nvs.get_raw(WIFI_SSID_NAMESPACE, &mut ssid_buf)?,
-                nvs.get_raw(WIFI_PASS_NAMESPACE, &mut pass_buf)?,
+                nvs.get_blob(WIFI_SSID_NAMESPACE)?,
+                nvs.get_blob(WIFI_PASS_NAMESPACE)?,
             ) {
                 Ok(Some(WifiCredentials {
-                    ssid: std::str::from_utf8(ssid)?.into(),
-                    pass: std::str::from_utf8(pass)?.into(),
+                    ssid: std::str::from_utf8(&ssid)?.into(),
+                    pass: std::str::from_utf8(&pass)?.into(),
                     channel: None, // We dont store channel in nvs
                 }))
             } else {
@@ -74,24 +64,20 @@ pub mod wifi_creds {
             }
         }
 
-        pub fn store(self) -> Result<(), EspError> {
+        pub fn store(self) -> Result<(), StorageError> {
             let mut nvs = Self::open_nvs()?;
 
-            nvs.set_raw(WIFI_SSID_NAMESPACE, self.ssid.as_bytes())?;
-            nvs.set_raw(WIFI_PASS_NAMESPACE, self.pass.as_bytes())?;
+            nvs.set_blob(WIFI_SSID_NAMESPACE, self.ssid.as_bytes())?;
+            nvs.set_blob(WIFI_PASS_NAMESPACE, self.pass.as_bytes())?;
 
             Ok(())
         }
 
-        fn open_nvs() -> Result<EspNvs<NvsDefault>, EspError> {
-            EspNvs::new(
-                EspNvsPartition::<NvsDefault>::take()?,
-                WIFI_CREDENTIALS_NAMESPACE,
-                true,
-            )
+        fn open_nvs() -> Result<NvsNamespace, StorageError> {
+            NvsNamespace::open(WIFI_CREDENTIALS_NAMESPACE)
         }
 
-        pub fn erase() -> Result<(), EspError> {
+        pub fn erase() -> Result<(), StorageError> {
             let mut nvs = Self::open_nvs()?;
 
             nvs.remove(WIFI_SSID_NAMESPACE)?;
@@ -102,11 +88,25 @@ pub mod wifi_creds {
     }
 }
 
+/// Platform part of wifi manager, which drives wifi hardware
+pub trait WifiDriver {
+    fn scan(&mut self) -> Result<Vec<AccessPointInfo>>;
+    /// Reconnects to network, failed connection leaves wifi started but not connected
+    fn connect(&mut self, creds: WifiCredentials) -> Result<()>;
+    /// Disconnects from network, access point keeps working
+    fn disconnect(&mut self) -> Result<()>;
+    /// Credentials of connected network, `None` if not connected
+    fn connected_creds(&self) -> Result<Option<WifiCredentials>>;
+}
+
+#[cfg(feature = "esp")]
 pub mod wifi_states {
     // use super::net_utils::ping;
     use super::wifi_creds::WifiCredentials;
-    use anyhow::Result;
-    use embedded_svc::wifi::{AccessPointInfo, Configuration, Wifi};
+    use super::WifiDriver;
+    use crate::T_CONFIG;
+    use anyhow::{anyhow, Result};
+    use embedded_svc::wifi::{AccessPointConfiguration, AccessPointInfo, Configuration, Wifi};
     use enum_dispatch::enum_dispatch;
     use esp_idf_hal::peripheral;
     use esp_idf_svc::eventloop::EspSystemEventLoop;
@@ -229,6 +229,148 @@ pub mod wifi_states {
             })
         }
     }
+
+    /// Wifi driver of esp, running access point and station at once
+    pub struct EspWifiDriver {
+        // Empty only if state transition failed
+        state: Option<WifiState>,
+    }
+
+    impl EspWifiDriver {
+        /// Starts access point and connects to stored network, if any
+        pub fn new(
+            modem: impl peripheral::Peripheral<P = esp_idf_hal::modem::Modem> + 'static,
+            sysloop: EspSystemEventLoop,
+        ) -> Result<Self> {
+            let wifi_creds = WifiCredentials::load()?;
+            let wifi_initial_cfg = Configuration::Mixed(
+                Default::default(),
+                AccessPointConfiguration {
+                    ssid: T_CONFIG.wifi_ap_ssid.into(),
+                    password: T_CONFIG.wifi_ap_pass.into(),
+                    auth_method: Default::default(),
+                    ..Default::default()
+                },
+            );
+
+            let wifi = WifiBase::new(modem, sysloop)?.configure(&wifi_initial_cfg)?;
+
+            let wifi_state = match wifi_creds {
+                Some(creds) => wifi.connect(creds)?,
+                None => WifiState::Started(wifi.start()?),
+            };
+
+            Ok(Self {
+                state: Some(wifi_state),
+            })
+        }
+
+        fn take_state(&mut self) -> Result<WifiState> {
+            self.state
+                .take()
+                .ok_or_else(|| anyhow!("Wifi is broken by failed state transition"))
+        }
+    }
+
+    impl WifiDriver for EspWifiDriver {
+        fn scan(&mut self) -> Result<Vec<AccessPointInfo>> {
+            match &mut self.state {
+                Some(state) => state.scan(),
+                None => Err(anyhow!("Wifi is broken by failed state transition")),
+            }
+        }
+
+        fn connect(&mut self, creds: WifiCredentials) -> Result<()> {
+            self.state = Some(match self.take_state()? {
+                WifiState::Started(m) => m.stop()?.connect(creds)?,
+                WifiState::Connected(m) => m.disconnect()?.stop()?.connect(creds)?,
+            });
+
+            Ok(())
+        }
+
+        fn disconnect(&mut self) -> Result<()> {
+            self.state = Some(match self.take_state()? {
+                WifiState::Connected(m) => WifiState::Started(m.disconnect()?.stop()?.start()?),
+                started => started,
+            });
+
+            Ok(())
+        }
+
+        fn connected_creds(&self) -> Result<Option<WifiCredentials>> {
+            match &self.state {
+                Some(WifiState::Connected(m)) => Ok(Some(m.get_creds()?)),
+                _ => Ok(None),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "host")]
+pub mod fake_wifi {
+    use super::wifi_creds::WifiCredentials;
+    use super::WifiDriver;
+    use anyhow::Result;
+    use embedded_svc::wifi::AccessPointInfo;
+    use log::info;
+
+    /// Networks visible to fake wifi, any password is accepted
+    const FAKE_NETWORKS: [&str; 3] = ["HomeNetwork", "Office", "CoffeeShop"];
+
+    /// Emulated wifi for host build, connects only to fake networks
+    #[derive(Default)]
+    pub struct FakeWifiDriver {
+        connected: Option<WifiCredentials>,
+    }
+
+    impl FakeWifiDriver {
+        /// Connects to stored network, if any
+        pub fn new() -> Result<Self> {
+            let mut driver = Self::default();
+            if let Some(creds) = WifiCredentials::load()? {
+                driver.connect(creds)?;
+            }
+
+            Ok(driver)
+        }
+    }
+
+    impl WifiDriver for FakeWifiDriver {
+        fn scan(&mut self) -> Result<Vec<AccessPointInfo>> {
+            Ok(FAKE_NETWORKS
+                .iter()
+                .enumerate()
+                .map(|(i, ssid)| AccessPointInfo {
+                    ssid: (*ssid).into(),
+                    channel: 1 + 5 * i as u8,
+                    ..Default::default()
+                })
+                .collect())
+        }
+
+        fn connect(&mut self, creds: WifiCredentials) -> Result<()> {
+            self.connected = if FAKE_NETWORKS.contains(&creds.ssid.as_str()) {
+                info!("Connected to fake network {}", creds.ssid);
+                Some(creds)
+            } else {
+                info!("Connection failed: no network {}", creds.ssid);
+                None
+            };
+
+            Ok(())
+        }
+
+        fn disconnect(&mut self) -> Result<()> {
+            self.connected = None;
+
+            Ok(())
+        }
+
+        fn connected_creds(&self) -> Result<Option<WifiCredentials>> {
+            Ok(self.connected.clone())
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -321,34 +463,17 @@ pub struct WifiManagerCommunication {
     pub store_credentials_api: StoreCredentials,
 }
 
-pub struct WifiManager {
-    pub state: WifiState,
+pub struct WifiManager<D> {
+    driver: D,
 }
 
-impl WifiManager {
-    pub fn new(
-        modem: impl peripheral::Peripheral<P = esp_idf_hal::modem::Modem> + 'static,
-        sysloop: EspSystemEventLoop,
-    ) -> Result<Self> {
-        let wifi_creds = WifiCredentials::load()?;
-        let wifi_initial_cfg = Configuration::Mixed(
-            Default::default(),
-            AccessPointConfiguration {
-                ssid: T_CONFIG.wifi_ap_ssid.into(),
-                password: T_CONFIG.wifi_ap_pass.into(),
-                auth_method: Default::default(),
-                ..Default::default()
-            },
-        );
-
-        let wifi = WifiBase::new(modem, sysloop)?.configure(&wifi_initial_cfg)?;
-
-        let wifi_state = match wifi_creds {
-            Some(creds) => wifi.connect(creds)?,
-            None => WifiState::Started(wifi.start()?),
-        };
+impl<D: WifiDriver + Send + 'static> WifiManager<D> {
+    pub fn new(driver: D) -> Self {
+        Self { driver }
+    }
 
-        Ok(Self { state: wifi_state })
+    pub fn is_connected(&self) -> Result<bool> {
+        Ok(self.driver.connected_creds()?.is_some())
     }
 
     pub fn daemon(self, stack_size: usize) -> Result<(JoinHandle<()>, WifiManagerCommunication)> {
@@ -362,66 +487,52 @@ impl WifiManager {
             std::thread::Builder::new()
                 .stack_size(stack_size)
                 .spawn(move || {
-                    let mut manager = self;
+                    let mut driver = self.driver;
                     loop {
                         match cmd_rx.recv().unwrap() {
-                            WifiManagerCmd::Scan => {
-                                scan_tx.send(manager.state.scan().unwrap()).unwrap()
-                            }
+                            WifiManagerCmd::Scan => scan_tx.send(driver.scan().unwrap()).unwrap(),
                             WifiManagerCmd::TryConnect(connect_args) => {
                                 std::thread::sleep(Duration::from_millis(1000));
-                                manager.state = match manager.state {
-                                    WifiState::Started(m) => {
-                                        m.stop().unwrap().connect(connect_args.creds).unwrap()
-                                    }
-                                    WifiState::Connected(m) => m
-                                        .disconnect()
-                                        .unwrap()
-                                        .stop()
-                                        .unwrap()
-                                        .connect(connect_args.creds)
-                                        .unwrap(),
-                                };
+                                driver.connect(connect_args.creds).unwrap();
                                 // Check if we connected and store credentials if requested
-                                match &manager.state {
-                                    WifiState::Connected(m) if connect_args.store_on_connect => {
-                                        m.get_creds().unwrap().store().unwrap();
+                                match driver.connected_creds().unwrap() {
+                                    Some(creds) if connect_args.store_on_connect => {
+                                        creds.store().unwrap();
                                     }
                                     _ => {}
                                 }
                             }
-                            WifiManagerCmd::Disconnect => match manager.state {
-                                WifiState::Connected(m) => {
+                            WifiManagerCmd::Disconnect => match driver.connected_creds().unwrap() {
+                                Some(_) => {
                                     disconnect_res_tx.send(Ok(())).unwrap();
                                     std::thread::sleep(Duration::from_millis(1000));
-                                    manager.state = WifiState::Started(
-                                        m.disconnect().unwrap().stop().unwrap().start().unwrap(),
-                                    );
+                                    driver.disconnect().unwrap();
                                 }
-                                WifiState::Started(_) => {
+                                None => {
                                     disconnect_res_tx.send(Err(APIError::NotConnected)).unwrap();
                                 }
                             },
                             WifiManagerCmd::GetStatus => {
-                                let status = match &manager.state {
-                                    WifiState::Started(_) => WifiStatus::Started,
-                                    WifiState::Connected(m) => WifiStatus::Connected {
-                                        ssid: m.get_creds().unwrap().ssid,
-                                    },
+                                let status = match driver.connected_creds().unwrap() {
+                                    None => WifiStatus::Started,
+                                    Some(creds) => WifiStatus::Connected { ssid: creds.ssid },
                                 };
                                 status_tx.send(status).unwrap()
                             }
-                            WifiManagerCmd::SaveCredentialsNvs => match &manager.state {
-                                WifiState::Started(_) => store_credentials_res_tx
-                                    .send(Err(APIError::NotConnected))
-                                    .unwrap(),
-                                WifiState::Connected(m) => {
-                                    m.get_creds().unwrap().store().unwrap();
-                                    store_credentials_res_tx.send(Ok(())).unwrap();
+                            WifiManagerCmd::SaveCredentialsNvs => {
+                                match driver.connected_creds().unwrap() {
+                                    None => store_credentials_res_tx
+                                        .send(Err(APIError::NotConnected))
+                                        .unwrap(),
+                                    Some(creds) => {
+                                        creds.store().unwrap();
+                                        store_credentials_res_tx.send(Ok(())).unwrap();
+                                    }
                                 }
-                            },
+                            }
                         }
-                        info!("daemon stack high water mark: {}", unsafe {
+                        #[cfg(feature = "esp")]
+                        log::info!("daemon stack high water mark: {}", unsafe {
                             esp_idf_sys::uxTaskGetStackHighWaterMark(std::ptr::null_mut())
                         });
                     }
@@ -0,0 +1,136 @@
+#![cfg(feature = "host")]
+
+mod common;
+
+use common::HostFirmware;
+
+#[test]
+fn set_conf_applies_partial_config() {
+    let firmware = HostFirmware::start();
+
+    let response = firmware.post("/set_conf", r#"{"fps": 30, "brightness": 100}"#);
+
+    assert_eq!(response.status, 200);
+    assert!(response.body.contains(r#""fps":30"#), "{}", response.body);
+    assert!(
+        response.body.contains(r#""brightness":100"#),
+        "{}",
+        response.body
+    );
+}
+
+#[test]
+fn set_conf_keeps_previous_values() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(firmware.post("/set_conf", r#"{"fps": 24}"#).status, 200);
+    let response = firmware.post("/set_conf", r#"{"white_brightness": 10}"#);
+
+    assert_eq!(response.status, 200);
+    assert!(response.body.contains(r#""fps":24"#), "{}", response.body);
+    assert!(
+        response.body.contains(r#""white_brightness":10"#),
+        "{}",
+        response.body
+    );
+}
+
+#[test]
+fn set_conf_rejects_malformed_json() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(firmware.post("/set_conf", r#"{"fps": 30"#).status, 400);
+    assert_eq!(firmware.post("/set_conf", "").status, 400);
+    assert_eq!(firmware.post("/set_conf", r#"{"fps": "fast"}"#).status, 400);
+    assert_eq!(firmware.post("/set_conf", r#"{"fps": 256}"#).status, 400);
+}
+
+#[test]
+fn set_conf_rejects_unknown_fields() {
+    let firmware = HostFirmware::start();
+
+    let response = firmware.post("/set_conf", r#"{"fps": 30, "speed": 2}"#);
+
+    assert_eq!(response.status, 400);
+    assert!(
+        response.body.contains(r#""code":"invalid_json""#),
+        "{}",
+        response.body
+    );
+    assert!(response.body.contains("unknown field"), "{}", response.body);
+}
+
+#[test]
+fn set_conf_rejects_invalid_values() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(firmware.post("/set_conf", r#"{"fps": 0}"#).status, 400);
+    assert_eq!(
+        firmware.post("/set_conf", r#"{"led_quantity": 0}"#).status,
+        400
+    );
+    // Host firmware drives single output of 150 leds
+    assert_eq!(
+        firmware
+            .post("/set_conf", r#"{"led_quantity": 151}"#)
+            .status,
+        400
+    );
+    assert_eq!(firmware.post("/set_conf", r#"{"gamma": 0.0}"#).status, 400);
+    assert_eq!(firmware.post("/set_conf", r#"{"gamma": 11.0}"#).status, 400);
+    assert_eq!(
+        firmware
+            .post(
+                "/set_conf",
+                r#"{"strip_type": {"color_order": "gbw", "white_channel": true}}"#
+            )
+            .status,
+        400
+    );
+    assert_eq!(
+        firmware
+            .post("/set_conf", r#"{"white_temperature_k": 100}"#)
+            .status,
+        400
+    );
+}
+
+#[test]
+fn reset_conf_restores_defaults() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(firmware.post("/set_conf", r#"{"fps": 24}"#).status, 200);
+    let response = firmware.post("/reset_conf", "");
+
+    assert_eq!(response.status, 200);
+    assert!(!response.body.contains(r#""fps":24"#), "{}", response.body);
+}
+
+#[test]
+fn unknown_route_is_not_found() {
+    let firmware = HostFirmware::start();
+
+    let response = firmware.get("/no_such_route");
+
+    assert_eq!(response.status, 404);
+    assert!(
+        response.body.contains(r#""code":"not_found""#),
+        "{}",
+        response.body
+    );
+    assert_eq!(firmware.get("/set_conf").status, 404);
+}
+
+#[test]
+fn set_conf_rejects_body_over_default_limit() {
+    let firmware = HostFirmware::start();
+
+    let response = firmware.post("/set_conf", &" ".repeat(8 * 1024 + 1));
+
+    assert_eq!(response.status, 413);
+    assert!(
+        response.body.contains(r#""code":"payload_too_large""#),
+        "{}",
+        response.body
+    );
+}
@@ -0,0 +1,45 @@
+#![cfg(feature = "host")]
+
+mod common;
+
+use common::HostFirmware;
+
+#[test]
+fn stored_outputs_are_reported_only_after_reboot() {
+    let firmware = HostFirmware::start();
+    let outputs_in_use = firmware.get("/outputs").body;
+
+    let outputs = r#"{"outputs":[{"pin":6,"rmt_channel":0,"led_quantity":150},{"pin":7,"rmt_channel":1,"led_quantity":60}],"mode":"independent"}"#;
+    assert_eq!(firmware.request("PUT", "/outputs", outputs).status, 200);
+
+    assert_eq!(firmware.get("/outputs").body, outputs_in_use);
+}
+
+#[test]
+fn outputs_reject_reserved_and_missing_pins() {
+    let firmware = HostFirmware::start();
+
+    for pin in [12, 15, 18, 22] {
+        let outputs = format!(
+            r#"{{"outputs": [{{"pin": {}, "rmt_channel": 0, "led_quantity": 150}}]}}"#,
+            pin
+        );
+        let response = firmware.request("PUT", "/outputs", &outputs);
+
+        assert_eq!(response.status, 400, "pin {}: {}", pin, response.body);
+    }
+}
+
+#[test]
+fn outputs_reject_invalid_led_quantity() {
+    let firmware = HostFirmware::start();
+
+    for led_quantity in [0, 1001] {
+        let outputs = format!(
+            r#"{{"outputs": [{{"pin": 6, "rmt_channel": 0, "led_quantity": {}}}]}}"#,
+            led_quantity
+        );
+
+        assert_eq!(firmware.request("PUT", "/outputs", &outputs).status, 400);
+    }
+}
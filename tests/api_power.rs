@@ -0,0 +1,55 @@
+#![cfg(feature = "host")]
+
+mod common;
+
+use common::HostFirmware;
+
+#[test]
+fn power_off_and_on_is_reported_in_status() {
+    let firmware = HostFirmware::start();
+
+    assert!(firmware.get("/status").body.contains(r#""on":true"#));
+
+    assert_eq!(firmware.post("/power", r#"{"on": false}"#).status, 200);
+    firmware.wait_for("/status", r#""on":false"#);
+
+    assert_eq!(
+        firmware
+            .post("/power", r#"{"on": true, "fade_ms": 100}"#)
+            .status,
+        200
+    );
+    firmware.wait_for("/status", r#""on":true"#);
+}
+
+#[test]
+fn power_off_blanks_led_strip() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(
+        firmware
+            .post("/set_conf", r#"{"white_brightness": 50}"#)
+            .status,
+        200
+    );
+    firmware.wait_for("/host/frame", "[0,0,0,50]");
+    assert_eq!(firmware.post("/power", r#"{"on": false}"#).status, 200);
+
+    let frame = firmware.wait_for("/host/frame", "[0,0,0,0]").body;
+    assert!(!frame.contains("50"), "{}", frame);
+}
+
+#[test]
+fn power_rejects_malformed_json() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(firmware.post("/power", "").status, 400);
+    assert_eq!(firmware.post("/power", r#"{"fade_ms": 100}"#).status, 400);
+    assert_eq!(firmware.post("/power", r#"{"on": "yes"}"#).status, 400);
+    assert_eq!(
+        firmware
+            .post("/power", r#"{"on": true, "speed": 2}"#)
+            .status,
+        400
+    );
+}
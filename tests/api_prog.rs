@@ -0,0 +1,244 @@
+#![cfg(feature = "host")]
+
+mod common;
+
+use common::{HostFirmware, LOOP_OFF_BYTECODE_BASE64, LOOP_OFF_PROG_BASE64};
+use prog_container::{Container, Metadata};
+
+#[test]
+fn send_prog_accepts_valid_program() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(
+        firmware
+            .post("/send_prog_base64", LOOP_OFF_PROG_BASE64)
+            .status,
+        200
+    );
+}
+
+#[test]
+fn send_prog_accepts_transition_params() {
+    let firmware = HostFirmware::start();
+
+    let response = firmware.post(
+        "/send_prog_base64?transition=crossfade&transition_ms=100&segment=0",
+        LOOP_OFF_PROG_BASE64,
+    );
+
+    assert_eq!(response.status, 200);
+}
+
+#[test]
+fn send_binary_prog_accepts_valid_program() {
+    let firmware = HostFirmware::start();
+
+    let container = base64::decode(LOOP_OFF_PROG_BASE64).unwrap();
+    let response = firmware.request_bytes("POST", "/send_prog?transition=crossfade", &container);
+
+    assert_eq!(response.status, 200);
+}
+
+#[test]
+fn send_prog_applies_default_fps_of_program() {
+    let firmware = HostFirmware::start();
+
+    let code = base64::decode(LOOP_OFF_BYTECODE_BASE64).unwrap();
+    let prog = Container::new(
+        Metadata {
+            default_fps: Some(24),
+            ..Default::default()
+        },
+        code,
+    )
+    .unwrap()
+    .encode();
+    assert_eq!(
+        firmware.request_bytes("POST", "/send_prog", &prog).status,
+        200
+    );
+
+    firmware.wait_for("/state", r#""fps":24"#);
+}
+
+#[test]
+fn send_binary_prog_rejects_bare_bytecode() {
+    let firmware = HostFirmware::start();
+
+    let bytecode = base64::decode(LOOP_OFF_BYTECODE_BASE64).unwrap();
+    let response = firmware.request_bytes("POST", "/send_prog", &bytecode);
+
+    assert_eq!(response.status, 400);
+    assert!(
+        response.body.contains(r#""code":"invalid_program""#),
+        "{}",
+        response.body
+    );
+}
+
+#[test]
+fn send_binary_prog_rejects_too_large_body() {
+    let firmware = HostFirmware::start();
+
+    let response = firmware.request_bytes("POST", "/send_prog", &[0; 4 * 1024 + 1]);
+
+    assert_eq!(response.status, 413);
+    assert!(
+        response.body.contains(r#""code":"payload_too_large""#),
+        "{}",
+        response.body
+    );
+}
+
+#[test]
+fn send_prog_rejects_malformed_base64() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(
+        firmware
+            .post("/send_prog_base64", "not base64 at all!")
+            .status,
+        400
+    );
+}
+
+#[test]
+fn send_prog_rejects_empty_program() {
+    let firmware = HostFirmware::start();
+
+    let response = firmware.post("/send_prog_base64", "");
+
+    assert_eq!(response.status, 400);
+    assert!(
+        response.body.contains(r#""code":"invalid_program""#),
+        "{}",
+        response.body
+    );
+    assert_eq!(firmware.request("PUT", "/programs/empty", "").status, 400);
+}
+
+#[test]
+fn send_prog_rejects_bare_bytecode() {
+    let firmware = HostFirmware::start();
+
+    let response = firmware.post("/send_prog_base64", LOOP_OFF_BYTECODE_BASE64);
+
+    assert_eq!(response.status, 400);
+    assert!(
+        response.body.contains(r#""code":"invalid_program""#),
+        "{}",
+        response.body
+    );
+    assert!(
+        response.body.contains("Not a program container"),
+        "{}",
+        response.body
+    );
+}
+
+#[test]
+fn send_prog_rejects_unknown_params() {
+    let firmware = HostFirmware::start();
+
+    let response = firmware.post("/send_prog_base64?speed=2", LOOP_OFF_PROG_BASE64);
+
+    assert_eq!(response.status, 400);
+    assert!(response.body.contains("unknown field"), "{}", response.body);
+}
+
+#[test]
+fn send_prog_rejects_invalid_params() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(
+        firmware
+            .post("/send_prog_base64?transition=spin", LOOP_OFF_PROG_BASE64)
+            .status,
+        400
+    );
+    assert_eq!(
+        firmware
+            .post("/send_prog_base64?transition_ms=-1", LOOP_OFF_PROG_BASE64)
+            .status,
+        400
+    );
+}
+
+#[test]
+fn program_library_round_trip() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(
+        firmware
+            .request("PUT", "/programs/off", LOOP_OFF_PROG_BASE64)
+            .status,
+        200
+    );
+    assert_eq!(
+        firmware.get("/programs").body,
+        r#"{"ok":true,"data":["off"]}"#
+    );
+    assert!(firmware
+        .get("/programs/off")
+        .body
+        .contains(LOOP_OFF_PROG_BASE64));
+    assert_eq!(firmware.post("/programs/off/play", "").status, 200);
+    assert_eq!(firmware.request("DELETE", "/programs/off", "").status, 200);
+    assert_eq!(firmware.get("/programs/off").status, 404);
+}
+
+#[test]
+fn program_library_rejects_invalid_names() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(
+        firmware
+            .request(
+                "PUT",
+                "/programs/far_too_long_program_name",
+                LOOP_OFF_PROG_BASE64
+            )
+            .status,
+        400
+    );
+    assert_eq!(firmware.post("/programs/missing/play", "").status, 404);
+}
+
+#[test]
+fn program_library_matches_only_exact_paths() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(
+        firmware
+            .request("PUT", "/programs/off", LOOP_OFF_PROG_BASE64)
+            .status,
+        200
+    );
+
+    assert_eq!(firmware.get("/programs/off/play").status, 404);
+    assert_eq!(
+        firmware.request("DELETE", "/programs/off/play", "").status,
+        404
+    );
+    assert_eq!(firmware.post("/programs/off/play/play", "").status, 404);
+    assert_eq!(firmware.post("/programs/off", "").status, 404);
+    assert_eq!(firmware.get("/programs/off").status, 200);
+}
+
+#[test]
+fn program_library_decodes_percent_encoded_names() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(
+        firmware
+            .request("PUT", "/programs/o%66f", LOOP_OFF_PROG_BASE64)
+            .status,
+        200
+    );
+
+    assert_eq!(
+        firmware.get("/programs").body,
+        r#"{"ok":true,"data":["off"]}"#
+    );
+    assert_eq!(firmware.get("/programs/a%2Fb").status, 400);
+}
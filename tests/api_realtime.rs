@@ -0,0 +1,42 @@
+#![cfg(feature = "host")]
+
+mod common;
+
+use common::HostFirmware;
+
+#[test]
+fn realtime_is_disabled_by_default() {
+    let firmware = HostFirmware::start();
+
+    let response = firmware.get("/realtime");
+
+    assert_eq!(response.status, 200);
+    assert!(
+        response
+            .body
+            .contains(r#""ddp":false,"e131":false,"art_net":false"#),
+        "{}",
+        response.body
+    );
+}
+
+#[test]
+fn realtime_keeps_missing_protocols_disabled() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(
+        firmware
+            .request("PUT", "/realtime", r#"{"e131": true}"#)
+            .status,
+        200
+    );
+    let response = firmware.get("/realtime");
+
+    assert!(
+        response
+            .body
+            .contains(r#""ddp":false,"e131":true,"art_net":false"#),
+        "{}",
+        response.body
+    );
+}
@@ -0,0 +1,59 @@
+#![cfg(feature = "host")]
+
+mod common;
+
+use common::{HostFirmware, LOOP_OFF_PROG_BASE64};
+
+#[test]
+fn segments_are_replaced_and_reset() {
+    let firmware = HostFirmware::start();
+
+    let layouts =
+        r#"[{"start":0,"length":60,"reversed":false},{"start":60,"length":90,"reversed":true}]"#;
+    assert_eq!(firmware.request("PUT", "/segments", layouts).status, 200);
+    assert!(firmware.get("/segments").body.contains(layouts));
+
+    assert_eq!(firmware.request("PUT", "/segments", "[]").status, 200);
+    assert!(firmware
+        .get("/segments")
+        .body
+        .contains(r#"[{"start":0,"length":150,"reversed":false}]"#));
+}
+
+#[test]
+fn segments_should_fit_into_strip() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(
+        firmware
+            .request("PUT", "/segments", r#"[{"start": 100, "length": 51}]"#)
+            .status,
+        400
+    );
+    assert_eq!(
+        firmware
+            .request(
+                "PUT",
+                "/segments",
+                r#"[{"start": 18446744073709551615, "length": 1, "reversed": true}]"#
+            )
+            .status,
+        400
+    );
+    assert_eq!(
+        firmware
+            .request("PUT", "/segments", r#"[{"start": 0, "length": 0}]"#)
+            .status,
+        400
+    );
+}
+
+#[test]
+fn program_for_unknown_segment_is_not_found() {
+    let firmware = HostFirmware::start();
+
+    let response = firmware.post("/send_prog_base64?segment=1", LOOP_OFF_PROG_BASE64);
+
+    assert_eq!(response.status, 404);
+    assert!(response.body.contains("not_found"), "{}", response.body);
+}
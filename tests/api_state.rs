@@ -0,0 +1,62 @@
+#![cfg(feature = "host")]
+
+mod common;
+
+use common::{HostFirmware, LOOP_OFF_PROG_BASE64};
+
+#[test]
+fn state_reports_applied_config() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(firmware.post("/set_conf", r#"{"fps": 30}"#).status, 200);
+    let response = firmware.get("/state");
+
+    assert_eq!(response.status, 200);
+    assert!(response.body.contains(r#""fps":30"#), "{}", response.body);
+    assert!(
+        response.body.contains(r#""uptime_s":"#),
+        "{}",
+        response.body
+    );
+    assert!(
+        response.body.contains(r#""measured_fps":"#),
+        "{}",
+        response.body
+    );
+}
+
+#[test]
+fn state_reports_vm_of_segments() {
+    let firmware = HostFirmware::start();
+
+    assert!(firmware.get("/state").body.contains(r#""vm":"running""#));
+
+    assert_eq!(firmware.post("/vm/pause", "").status, 200);
+    firmware.wait_for("/state", r#""vm":"paused""#);
+}
+
+#[test]
+fn state_reports_no_rendered_frames_while_paused() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(firmware.post("/vm/pause", "").status, 200);
+
+    firmware.wait_for("/state", r#""measured_fps":0.0"#);
+}
+
+#[test]
+fn state_reports_name_of_played_program() {
+    let firmware = HostFirmware::start();
+
+    assert!(firmware.get("/state").body.contains(r#""name":null"#));
+
+    assert_eq!(
+        firmware
+            .request("PUT", "/programs/off", LOOP_OFF_PROG_BASE64)
+            .status,
+        200
+    );
+    assert_eq!(firmware.post("/programs/off/play", "").status, 200);
+
+    firmware.wait_for("/state", r#""name":"off""#);
+}
@@ -0,0 +1,142 @@
+#![cfg(feature = "host")]
+
+mod common;
+
+use common::{container, HostFirmware, FAULTING_BYTECODE};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+// Many frames are rendered in this time at default 60 fps
+const SETTLE_TIME: Duration = Duration::from_millis(300);
+
+fn wait_for_frames(firmware: &HostFirmware, expected: usize) {
+    let started = Instant::now();
+    while firmware.recorded_frames() < expected {
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "Expected {} frames",
+            expected
+        );
+        sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn vm_pause_step_and_resume() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(firmware.post("/vm/pause", "").status, 200);
+    assert_eq!(firmware.post("/vm/step", "").status, 200);
+    assert_eq!(firmware.post("/vm/step", "").status, 200);
+    assert_eq!(firmware.post("/vm/resume", "").status, 200);
+}
+
+#[test]
+fn paused_vm_keeps_frame_and_steps_single_frame() {
+    let firmware = HostFirmware::start();
+    wait_for_frames(&firmware, 1);
+
+    assert_eq!(firmware.post("/vm/pause", "").status, 200);
+    assert!(firmware.get("/state").body.contains(r#""vm":"paused""#));
+    assert_eq!(firmware.request("DELETE", "/host/frames", "").status, 200);
+
+    // Strip keeps last frame, no new frames are rendered
+    sleep(SETTLE_TIME);
+    assert_eq!(firmware.recorded_frames(), 0);
+
+    for steps in 1..=2 {
+        assert_eq!(firmware.post("/vm/step", "").status, 200);
+        wait_for_frames(&firmware, steps);
+        sleep(SETTLE_TIME);
+        assert_eq!(firmware.recorded_frames(), steps);
+        assert!(firmware.get("/state").body.contains(r#""vm":"paused""#));
+    }
+
+    assert_eq!(firmware.post("/vm/resume", "").status, 200);
+    wait_for_frames(&firmware, 10);
+    assert!(firmware.get("/state").body.contains(r#""vm":"running""#));
+}
+
+#[test]
+fn vm_step_is_rejected_while_strip_is_off() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(firmware.post("/power", r#"{"on": false}"#).status, 200);
+    let response = firmware.post("/vm/step", "");
+    assert_eq!(response.status, 409);
+    assert!(response.body.contains("rejected"), "{}", response.body);
+
+    // Pause and resume do not need running programs
+    assert_eq!(firmware.post("/vm/pause", "").status, 200);
+    assert_eq!(firmware.post("/vm/resume", "").status, 200);
+
+    assert_eq!(firmware.post("/power", r#"{"on": true}"#).status, 200);
+    assert_eq!(firmware.post("/vm/step", "").status, 200);
+}
+
+#[test]
+fn vm_control_of_single_segment() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(firmware.post("/vm/pause?segment=0", "").status, 200);
+    assert_eq!(firmware.post("/vm/resume?segment=0", "").status, 200);
+}
+
+#[test]
+fn vm_control_rejects_unknown_segment() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(firmware.post("/vm/pause?segment=5", "").status, 404);
+    assert_eq!(firmware.post("/vm/step?segment=x", "").status, 400);
+    assert_eq!(firmware.post("/vm/resume?speed=2", "").status, 400);
+}
+
+#[test]
+fn vm_faults_are_listed_and_cleared() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(firmware.get("/faults").body, r#"{"ok":true,"data":[]}"#);
+    assert_eq!(firmware.request("DELETE", "/faults", "").status, 200);
+    assert_eq!(firmware.get("/faults").body, r#"{"ok":true,"data":[]}"#);
+}
+
+#[test]
+fn vm_runtime_fault_is_reported() {
+    let firmware = HostFirmware::start();
+
+    let response =
+        firmware.request_bytes("POST", "/send_prog", &container("spin", FAULTING_BYTECODE));
+    assert_eq!(response.status, 200, "{}", response.body);
+
+    let faults = firmware.wait_for("/faults", r#""name":"spin""#).body;
+    assert!(faults.contains(r#""segment":0"#), "{}", faults);
+    assert!(faults.contains(r#""pc":1"#), "{}", faults);
+    assert!(faults.contains(r#""error":""#), "{}", faults);
+    firmware.wait_for("/state", r#""vm":"stopped""#);
+}
+
+#[test]
+fn vm_faults_drop_oldest_when_full() {
+    let firmware = HostFirmware::start();
+
+    // One fault more than firmware keeps
+    for i in 0..=16 {
+        let name = format!("spin_{}", i);
+        let prog = container(&name, FAULTING_BYTECODE);
+        assert_eq!(
+            firmware.request_bytes("POST", "/send_prog", &prog).status,
+            200
+        );
+        firmware.wait_for("/faults", &format!(r#""name":"{}""#, name));
+    }
+
+    let faults = firmware.get("/faults").body;
+    assert_eq!(faults.matches(r#""pc":"#).count(), 16, "{}", faults);
+    assert!(!faults.contains(r#""name":"spin_0""#), "{}", faults);
+    // Oldest first
+    assert!(
+        faults.find(r#""name":"spin_1""#) < faults.find(r#""name":"spin_16""#),
+        "{}",
+        faults
+    );
+}
@@ -0,0 +1,112 @@
+#![cfg(feature = "host")]
+
+mod common;
+
+use common::HostFirmware;
+
+#[test]
+fn scan_lists_fake_networks() {
+    let firmware = HostFirmware::start();
+
+    let response = firmware.get("/wifi/scan");
+
+    assert_eq!(response.status, 200);
+    assert!(response.body.contains("HomeNetwork"), "{}", response.body);
+}
+
+#[test]
+fn status_is_started_without_credentials() {
+    let firmware = HostFirmware::start();
+
+    let response = firmware.get("/wifi/status");
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, r#"{"ok":true,"data":{"type":"started"}}"#);
+}
+
+#[test]
+fn connect_and_disconnect() {
+    let firmware = HostFirmware::start();
+
+    let response = firmware.post(
+        "/wifi/connect",
+        r#"{"creds": {"ssid": "HomeNetwork", "pass": "secret"}, "store_on_connect": false}"#,
+    );
+    assert_eq!(response.status, 200);
+
+    firmware.wait_for("/wifi/status", r#""ssid":"HomeNetwork""#);
+    assert_eq!(firmware.post("/wifi/store_credentials", "").status, 200);
+
+    assert_eq!(firmware.post("/wifi/disconnect", "").status, 200);
+    firmware.wait_for("/wifi/status", r#"{"type":"started"}"#);
+}
+
+#[test]
+fn connect_to_missing_network_stays_started() {
+    let firmware = HostFirmware::start();
+
+    let response = firmware.post(
+        "/wifi/connect",
+        r#"{"creds": {"ssid": "Nowhere", "pass": "secret"}, "store_on_connect": true}"#,
+    );
+    assert_eq!(response.status, 200);
+
+    assert_eq!(
+        firmware.get("/wifi/status").body,
+        r#"{"ok":true,"data":{"type":"started"}}"#
+    );
+}
+
+#[test]
+fn connect_rejects_malformed_body() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(firmware.post("/wifi/connect", "").status, 400);
+    assert_eq!(firmware.post("/wifi/connect", r#"{"creds": "#).status, 400);
+    assert_eq!(
+        firmware
+            .post("/wifi/connect", r#"{"creds": {"ssid": "HomeNetwork"}}"#)
+            .status,
+        400
+    );
+}
+
+#[test]
+fn connect_rejects_unknown_credential_fields() {
+    let firmware = HostFirmware::start();
+
+    let response = firmware.post(
+        "/wifi/connect",
+        r#"{"creds": {"ssid": "HomeNetwork", "password": "secret"}, "store_on_connect": false}"#,
+    );
+
+    assert_eq!(response.status, 400);
+    assert!(response.body.contains("unknown field"), "{}", response.body);
+}
+
+#[test]
+fn disconnect_requires_connection() {
+    let firmware = HostFirmware::start();
+
+    let response = firmware.post("/wifi/disconnect", "");
+
+    assert_eq!(response.status, 409);
+    assert_eq!(
+        response.body,
+        r#"{"ok":false,"error":{"code":"rejected","message":"Wifi is not connected"}}"#
+    );
+}
+
+#[test]
+fn store_credentials_requires_connection() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(firmware.post("/wifi/store_credentials", "").status, 409);
+}
+
+#[test]
+fn erase_credentials() {
+    let firmware = HostFirmware::start();
+
+    assert_eq!(firmware.post("/wifi/erase_credentials", "").status, 200);
+}
@@ -0,0 +1,153 @@
+#![allow(dead_code)]
+
+use prog_container::{Container, Metadata};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// Program which turns led strip off, same as firmware uses by default, wrapped in program container
+pub const LOOP_OFF_PROG_BASE64: &str = "QUxORwEBAAAAFAAAAOBQDwAgEQGBEOMBcUABAAHkQAAAmWW9Og==";
+/// Bare bytecode of same program, without container
+pub const LOOP_OFF_BYTECODE_BASE64: &str = "4FAPACARAYEQ4wFxQAEAAeRAAAA=";
+/// Bytecode which shows first frame (`blit`) and then jumps to itself forever (`jmp 1`),
+/// so it passes validation and exceeds instruction limit on second frame
+pub const FAULTING_BYTECODE: &[u8] = &[0xe4, 0x40, 0x01, 0x00];
+
+/// Program container with given name, as produced by frontend
+pub fn container(name: &str, code: &[u8]) -> Vec<u8> {
+    Container::new(
+        Metadata {
+            name: name.to_string(),
+            ..Default::default()
+        },
+        code.to_vec(),
+    )
+    .unwrap()
+    .encode()
+}
+
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+/// Host build of firmware, running in separate process with its own in-memory storage
+pub struct HostFirmware {
+    child: Child,
+    addr: String,
+}
+
+impl HostFirmware {
+    pub fn start() -> Self {
+        // Let os pick free port
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+
+        let child = Command::new(env!("CARGO_BIN_EXE_esp-rust-lighting"))
+            .env("HOST_HTTP_ADDR", &addr)
+            .spawn()
+            .expect("Failed to start host firmware");
+
+        let firmware = Self { child, addr };
+
+        let started = Instant::now();
+        while TcpStream::connect(&firmware.addr).is_err() {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "Host firmware did not start listening on {}",
+                firmware.addr
+            );
+            sleep(Duration::from_millis(50));
+        }
+
+        firmware
+    }
+
+    pub fn request(&self, method: &str, uri: &str, body: &str) -> Response {
+        self.request_bytes(method, uri, body.as_bytes())
+    }
+
+    pub fn request_bytes(&self, method: &str, uri: &str, body: &[u8]) -> Response {
+        let mut stream = TcpStream::connect(&self.addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+            method,
+            uri,
+            self.addr,
+            body.len()
+        )
+        .unwrap();
+        // Server may reject body without reading it
+        let _ = stream.write_all(body);
+
+        let mut raw = String::new();
+        stream.read_to_string(&mut raw).unwrap();
+
+        let (head, body) = raw.split_once("\r\n\r\n").unwrap_or((&raw, ""));
+        let status = head
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .unwrap_or_else(|| panic!("Malformed response: {}", raw));
+
+        Response {
+            status,
+            body: body.to_string(),
+        }
+    }
+
+    pub fn get(&self, uri: &str) -> Response {
+        self.request("GET", uri, "")
+    }
+
+    pub fn post(&self, uri: &str, body: &str) -> Response {
+        self.request("POST", uri, body)
+    }
+
+    /// Frames written to first emulated led output since last `DELETE /host/frames`
+    pub fn recorded_frames(&self) -> usize {
+        let response = self.get("/host/strip.ppm");
+        assert_eq!(response.status, 200);
+
+        // Height of image in header "P6\n{width} {height}\n255\n" is number of frames
+        response
+            .body
+            .lines()
+            .nth(1)
+            .and_then(|size| size.split_whitespace().nth(1))
+            .and_then(|height| height.parse().ok())
+            .unwrap_or_else(|| panic!("Malformed ppm: {:?}", response.body))
+    }
+
+    /// Polls `uri` until its body contains `expected`
+    pub fn wait_for(&self, uri: &str, expected: &str) -> Response {
+        let started = Instant::now();
+        loop {
+            let response = self.get(uri);
+            if response.body.contains(expected) {
+                return response;
+            }
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "{} did not return {}, last response: {}",
+                uri,
+                expected,
+                response.body
+            );
+            sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+impl Drop for HostFirmware {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}